@@ -0,0 +1,180 @@
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new<N: ToString, T: ToString>(name: N, text: T) -> Source {
+        Source {
+            name: name.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    /// Returns the 1-based line and column of the byte `offset`.
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let before = &self.text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before[self.line_start(offset)..].chars().count() + 1;
+        (line, column)
+    }
+
+    fn line_start(&self, offset: usize) -> usize {
+        self.text[..offset].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self, offset: usize) -> usize {
+        self.text[offset..]
+            .find('\n')
+            .map_or(self.text.len(), |i| offset + i)
+    }
+}
+
+/// A byte range of a source.
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub source: Arc<Source>,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Location {
+    pub fn new(source: Arc<Source>, start: usize, end: usize) -> Location {
+        Location { source, start, end }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (line, column) = self.source.position(self.start);
+        write!(f, "{}:{}:{}", self.source.name, line, column)
+    }
+}
+
+/// An error pointing at a location, rendered with an excerpt of the offending line.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub location: Location,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new<S: ToString>(message: S, location: Location) -> Diagnostic {
+        Diagnostic {
+            message: message.to_string(),
+            location,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_note<S: ToString>(mut self, note: S) -> Diagnostic {
+        self.notes.push(note.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Location { source, start, end } = &self.location;
+        let (line, _) = source.position(*start);
+        let line_start = source.line_start(*start);
+        let line_end = source.line_end(*start);
+
+        // Keep tabs in the padding so that the caret lines up with the excerpt.
+        let padding: String = source.text[line_start..*start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source.text[*start..(*end).max(*start).min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+
+        let gutter = line.to_string().len();
+        writeln!(f, "{}", self.message)?;
+        writeln!(f, "{:gutter$}--> {}", "", self.location, gutter = gutter)?;
+        writeln!(f, "{:gutter$} |", "", gutter = gutter)?;
+        writeln!(f, "{} | {}", line, &source.text[line_start..line_end])?;
+        write!(
+            f,
+            "{:gutter$} | {}{}",
+            "",
+            padding,
+            "^".repeat(width),
+            gutter = gutter
+        )?;
+        for note in &self.notes {
+            write!(f, "\n{:gutter$} = {}", "", note, gutter = gutter)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Diagnostic {}
+
+/// Picks the candidate closest to `name`, if any is close enough to be a likely typo.
+/// Names shorter than three characters get no suggestion.
+pub fn suggest<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let limit = name.chars().count() / 3;
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[test]
+fn test_position() {
+    let source = Source::new("test.spc", "a: 1,\nb: 2,\n\ta + b");
+    assert_eq!(source.position(0), (1, 1));
+    assert_eq!(source.position(3), (1, 4));
+    assert_eq!(source.position(6), (2, 1));
+    assert_eq!(source.position(13), (3, 2));
+}
+
+#[test]
+fn test_render() {
+    let source = Arc::new(Source::new("test.spc", "a: 1,\na + )"));
+    let diagnostic = Diagnostic::new(
+        "expected expression, found `)`",
+        Location::new(source, 10, 11),
+    );
+    assert_eq!(
+        diagnostic.to_string(),
+        "expected expression, found `)`\n --> test.spc:2:5\n  |\n2 | a + )\n  |     ^"
+    );
+}
+
+#[test]
+fn test_suggest() {
+    let names = vec!["fib", "fizzbuzz", "range"];
+    assert_eq!(suggest("fob", names.clone()), Some("fib"));
+    assert_eq!(suggest("rang", names.clone()), Some("range"));
+    assert_eq!(suggest("foo", names.clone()), None);
+    assert_eq!(suggest("fi", names), None);
+}
@@ -0,0 +1,334 @@
+use crate::diagnostic::{Diagnostic, Source};
+use crate::host::{HostFunction, HostModule};
+use crate::module::Library;
+use crate::parser;
+use crate::translator::{self, Errors};
+use crate::vm::{self, Value, VM};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Why a program could not be evaluated.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The program file could not be read.
+    Io(PathBuf, io::Error),
+    /// The program is not valid spctr.
+    Syntax(Diagnostic),
+    /// The program refers to unknown identifiers or modules, among other mistakes found before running it.
+    Translation(Vec<Diagnostic>),
+    /// The program failed while running.
+    Runtime(vm::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "cannot read `{}`: {}", path.display(), e),
+            Error::Syntax(diagnostic) => write!(f, "{}", diagnostic),
+            Error::Translation(diagnostics) => {
+                let diagnostics: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+                write!(f, "{}", diagnostics.join("\n\n"))
+            }
+            Error::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<Errors> for Error {
+    fn from(errors: Errors) -> Error {
+        Error::Translation(errors.0.iter().map(|e| e.diagnostic()).collect())
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Error {
+        match e.downcast::<vm::Error>() {
+            Ok(e) => Error::Runtime(e),
+            Err(e) => Error::Runtime(vm::Error {
+                message: e.to_string(),
+                location: None,
+                trace: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Evaluates spctr programs with the library directories and bindings set up on it.
+#[derive(Clone, Default)]
+pub struct Engine {
+    library_dirs: Vec<PathBuf>,
+    globals: Vec<(String, Value)>,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    /// Searches `dir` for imports of bare paths, after the directories added before
+    /// and before those listed in `SPCTR_PATH`.
+    pub fn add_library_dir<P: Into<PathBuf>>(&mut self, dir: P) -> &mut Engine {
+        self.library_dirs.push(dir.into());
+        self
+    }
+
+    /// Binds `value` to `name` alongside the prelude of every program.
+    pub fn bind<S: ToString>(&mut self, name: S, value: Value) -> &mut Engine {
+        self.globals.push((name.to_string(), value));
+        self
+    }
+
+    /// Binds the Rust function `f` to `name`, so that programs can call it like a spctr function.
+    pub fn register_fn<S, P, F>(&mut self, name: S, f: F) -> &mut Engine
+    where
+        S: ToString,
+        F: HostFunction<P>,
+    {
+        self.bind(name, Value::function(f.into_function()))
+    }
+
+    /// Binds the functions of `module` to `name` as a block, as the prelude does with `List` and `String`.
+    pub fn register_module<S: ToString>(&mut self, name: S, module: HostModule) -> &mut Engine {
+        self.bind(name, module.into_value())
+    }
+
+    /// Evaluates the program `text` in full.
+    pub fn eval_str(&self, text: &str) -> Result<Value, Error> {
+        self.eval_source("<string>", text)
+    }
+
+    /// Evaluates the program `text`, naming it `name` in error messages
+    /// and resolving its relative imports from the directory of `name`.
+    pub fn eval_source(&self, name: &str, text: &str) -> Result<Value, Error> {
+        let source = Arc::new(Source::new(name, text));
+        let ast = parser::parse(&source).map_err(Error::Syntax)?;
+        let cmd = translator::get_cmd(source, &ast, self.globals.clone(), &self.library())?;
+        Ok(vm::run(cmd)?)
+    }
+
+    pub fn eval_file<P: AsRef<Path>>(&self, path: P) -> Result<Value, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| Error::Io(path.to_path_buf(), e))?;
+        self.eval_source(&path.display().to_string(), &text)
+    }
+
+    /// Starts a session evaluating entries one after another, as in the REPL.
+    pub fn session(&self) -> Result<Session, Error> {
+        let (session, prelude) = translator::Session::new(self.globals.clone(), self.library())?;
+        let mut vm = VM::new();
+        vm.execute(prelude)?;
+        Ok(Session { session, vm })
+    }
+
+    fn library(&self) -> Library {
+        Library::new(self.library_dirs.clone())
+    }
+}
+
+/// Entries evaluated one after another, where binds defined by an entry stay visible to the entries after it.
+pub struct Session {
+    session: translator::Session,
+    vm: VM,
+}
+
+impl Session {
+    /// Evaluates an entry, `a: 1, b: 2` or `a: 1, a + 1`, returning the value of its body if it has one.
+    /// The binds of an entry are kept only when it is evaluated successfully.
+    pub fn eval(&mut self, name: &str, text: &str) -> Result<Option<Value>, Error> {
+        let source = Arc::new(Source::new(name, text));
+        let entry = parser::parse_entry(&source).map_err(Error::Syntax)?;
+
+        // The VM discards the scope of a failed entry, so its binds are committed only on success.
+        let mut next = self.session.clone();
+        let cmd = next.translate(source, &entry)?;
+        let value = if entry.body.is_some() {
+            Some(self.vm.run(cmd)?)
+        } else {
+            self.vm.execute(cmd)?;
+            None
+        };
+        self.session = next;
+        Ok(value)
+    }
+}
+
+#[test]
+fn test_engine() {
+    let mut engine = Engine::new();
+    engine.bind("http_port", Value::number(8080.0));
+    let value = engine
+        .eval_str("{name: \"web\", port: http_port, tls: port = 443}")
+        .unwrap();
+    assert_eq!(value.get("name").unwrap().as_str(), Some("web"));
+    assert_eq!(value.get("port").unwrap().as_number(), Some(8080.0));
+    assert_eq!(value.get("tls").unwrap().as_bool(), Some(false));
+    assert!(value.get("missing").is_none());
+
+    let value = engine.eval_file("examples/modules/main.spc").unwrap();
+    assert_eq!(value.get("replicas").unwrap().as_number(), Some(4.0));
+
+    match engine.eval_str("1 +").unwrap_err() {
+        Error::Syntax(d) => assert_eq!(d.message, "expected expression, found end of input"),
+        e => panic!("{:?}", e),
+    }
+    match engine.eval_str("x").unwrap_err() {
+        Error::Translation(d) => assert_eq!(d[0].message, "unknown identifier `x`"),
+        e => panic!("{:?}", e),
+    }
+    match engine.eval_str("[][0]").unwrap_err() {
+        Error::Runtime(e) => assert_eq!(e.message, "index 0 out of range for list of length 0"),
+        e => panic!("{:?}", e),
+    }
+    match engine.eval_file("no/such.spc").unwrap_err() {
+        Error::Io(path, _) => assert_eq!(path, Path::new("no/such.spc")),
+        e => panic!("{:?}", e),
+    }
+}
+
+#[test]
+fn test_host_functions() {
+    use std::rc::Rc;
+
+    let mut engine = Engine::new();
+    engine.register_fn("repeat", |s: Rc<String>, n: f64| {
+        Ok(Value::string(Rc::new(s.repeat(n as usize))))
+    });
+    engine.register_module(
+        "Math",
+        HostModule::new()
+            .function("max", |a: f64, b: f64| Ok(Value::number(a.max(b))))
+            .function("pi", || Ok(Value::number(std::f64::consts::PI)))
+            .function("or_zero", |n: Option<f64>| {
+                Ok(Value::number(n.unwrap_or(0.0)))
+            }),
+    );
+    let value = engine
+        .eval_str("[repeat(\"ab\", 2), Math.max(1, 3), Math.pi() > 3, Math.or_zero(null)]")
+        .unwrap();
+    assert_eq!(value.to_string(), "[\"abab\", 3, true, 0]");
+
+    let error = |text| match engine.eval_str(text).unwrap_err() {
+        Error::Runtime(e) => (e.message, e.location.unwrap().to_string()),
+        e => panic!("{:?}", e),
+    };
+    assert_eq!(
+        error("repeat(\"ab\")"),
+        (
+            "function takes 2 arguments but 1 was given".to_string(),
+            "<string>:1:1".to_string()
+        )
+    );
+    assert_eq!(
+        error("x: Math.max(1, \"2\"), x"),
+        (
+            "argument 2: expected number, found string".to_string(),
+            "<string>:1:4".to_string()
+        )
+    );
+}
+
+#[test]
+fn test_callbacks() {
+    use crate::host::Caller;
+
+    let mut engine = Engine::new();
+    engine.register_fn(
+        "try",
+        |caller: &mut Caller<'_>, f: Value, default: Value| {
+            Ok(caller.call(&f, Vec::new()).unwrap_or(default))
+        },
+    );
+    engine.register_fn("port_of", |caller: &mut Caller<'_>, b: Value| {
+        Ok(caller.get(&b, "port")?.unwrap_or_else(Value::null))
+    });
+    engine.register_fn("fields", |caller: &mut Caller<'_>, b: Value| {
+        caller.force(&b)?;
+        Ok(Value::number(b.into_fields()?.len() as f64))
+    });
+    let value = engine
+        .eval_str("[port_of({port: 1}), port_of({}), fields({a: 1, b: {c: 2}})]")
+        .unwrap();
+    assert_eq!(value.to_string(), "[1, null, 2]");
+    let value = engine
+        .eval_str(
+            "[List.map(List.filter([1, 2, 3], (x) => x > 1), (x) => x * 2), try(() => [][0], 5)]",
+        )
+        .unwrap();
+    assert_eq!(value.to_string(), "[[4, 6], 5]");
+
+    match engine.eval_str("List.filter([1], (x) => x)").unwrap_err() {
+        Error::Runtime(e) => {
+            assert_eq!(
+                e.message,
+                "filter predicate must return a bool, found number"
+            )
+        }
+        e => panic!("{:?}", e),
+    }
+    match engine
+        .eval_str("f: (x) => x.y,\nList.map([1], f)")
+        .unwrap_err()
+    {
+        Error::Runtime(e) => {
+            assert_eq!(e.message, "cannot access field `y` of number");
+            assert_eq!(e.location.unwrap().to_string(), "<string>:1:11");
+            let trace: Vec<_> = e.trace.iter().map(ToString::to_string).collect();
+            assert_eq!(trace, vec!["in call `List.map([1], f)` at <string>:2:1"]);
+        }
+        e => panic!("{:?}", e),
+    }
+}
+
+#[test]
+fn test_session() {
+    let mut engine = Engine::new();
+    engine.bind("http_port", Value::number(8080.0));
+    let mut session = engine.session().unwrap();
+    assert_eq!(
+        session
+            .eval("<test>", "http_port")
+            .unwrap()
+            .unwrap()
+            .as_number(),
+        Some(8080.0)
+    );
+    assert!(session.eval("<test>", "a: 1").unwrap().is_none());
+    assert!(session.eval("<test>", "b: 1, [][0]").is_err());
+    assert!(session.eval("<test>", "c: 5").unwrap().is_none());
+    // Fails only when the fields of the result are evaluated.
+    assert!(session.eval("<test>", "b: {x: [][0]}, b").is_err());
+    assert_eq!(
+        session
+            .eval("<test>", "[a, c]")
+            .unwrap()
+            .unwrap()
+            .to_string(),
+        "[1, 5]"
+    );
+    assert!(matches!(
+        session.eval("<test>", "b"),
+        Err(Error::Translation(_))
+    ));
+    assert_eq!(
+        session
+            .eval("<test>", "a + 1")
+            .unwrap()
+            .unwrap()
+            .as_number(),
+        Some(2.0)
+    );
+}
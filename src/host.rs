@@ -0,0 +1,158 @@
+use crate::vm::{self, ForeignFunction, Function, Value, VM};
+use anyhow::{anyhow, Result};
+use std::rc::Rc;
+
+/// Conversion of the arguments of host functions from spctr values.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Value> {
+        Ok(value)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<f64> {
+        value.into_number()
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<bool> {
+        value.into_bool()
+    }
+}
+
+impl FromValue for Rc<String> {
+    fn from_value(value: Value) -> Result<Rc<String>> {
+        value.into_string()
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<String> {
+        Ok((*value.into_string()?).clone())
+    }
+}
+
+impl FromValue for Rc<Vec<Value>> {
+    fn from_value(value: Value) -> Result<Rc<Vec<Value>>> {
+        value.into_list()
+    }
+}
+
+/// `null` as `None`, and any other value as converted to `T`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Option<T>> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+/// The running program, as seen by host functions which take it as their first parameter.
+pub struct Caller<'a> {
+    vm: &'a mut VM,
+}
+
+impl Caller<'_> {
+    /// Calls the spctr function `f` with `args`.
+    /// Errors raised within `f` keep their location, with this host function's call in their trace.
+    pub fn call(&mut self, f: &Value, args: Vec<Value>) -> Result<Value> {
+        self.vm.call_function(f.clone(), args)
+    }
+
+    /// The value of the field `name` of a block, evaluating it if needed, or `None` if there is no such field.
+    pub fn get(&mut self, value: &Value, name: &str) -> Result<Option<Value>> {
+        self.vm.field(value, name)
+    }
+
+    /// Evaluates every field of the blocks within `value`, as needed before `Value::into_fields`.
+    pub fn force(&mut self, value: &Value) -> Result<()> {
+        self.vm.force_nested(value)
+    }
+}
+
+/// Rust functions and closures which can be called from spctr, taking parameters of `FromValue` types.
+/// They may take a `&mut Caller` first to call back into spctr functions.
+/// The arity and the types of the arguments are checked on each call.
+pub trait HostFunction<Params> {
+    fn into_function(self) -> Function;
+}
+
+macro_rules! impl_host_function {
+    ($($param:ident),*) => {
+        impl<F, $($param),*> HostFunction<($($param,)*)> for F
+        where
+            F: Fn($($param),*) -> Result<Value> + 'static,
+            $($param: FromValue,)*
+        {
+            #[allow(non_snake_case)]
+            fn into_function(self) -> Function {
+                HostFunction::<(Caller<'static>, $($param,)*)>::into_function(
+                    move |_: &mut Caller<'_>, $($param: $param),*| self($($param),*),
+                )
+            }
+        }
+
+        impl<F, $($param),*> HostFunction<(Caller<'static>, $($param,)*)> for F
+        where
+            F: Fn(&mut Caller<'_>, $($param),*) -> Result<Value> + 'static,
+            $($param: FromValue,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_function(self) -> Function {
+                let arity = 0 $(+ { let _ = stringify!($param); 1 })*;
+                Function::Foreign(ForeignFunction(Rc::new(move |vm: &mut VM, args: Vec<Value>| {
+                    if args.len() != arity {
+                        return Err(vm::arity_error(arity, args.len()));
+                    }
+                    let mut args = args.into_iter().enumerate();
+                    $(
+                        let (i, arg) = args.next().unwrap();
+                        let $param = $param::from_value(arg)
+                            .map_err(|e| anyhow!("argument {}: {}", i + 1, e))?;
+                    )*
+                    self(&mut Caller { vm }, $($param),*)
+                })))
+            }
+        }
+    };
+}
+
+impl_host_function!();
+impl_host_function!(A);
+impl_host_function!(A, B);
+impl_host_function!(A, B, C);
+impl_host_function!(A, B, C, D);
+impl_host_function!(A, B, C, D, E);
+impl_host_function!(A, B, C, D, E, G);
+
+/// Host functions registered under one name, which spctr sees as a block of functions like `List`.
+#[derive(Clone, Default)]
+pub struct HostModule {
+    functions: Vec<(String, Value)>,
+}
+
+impl HostModule {
+    pub fn new() -> HostModule {
+        HostModule::default()
+    }
+
+    pub fn function<S, P, F>(mut self, name: S, f: F) -> HostModule
+    where
+        S: ToString,
+        F: HostFunction<P>,
+    {
+        self.functions
+            .push((name.to_string(), Value::function(f.into_function())));
+        self
+    }
+
+    pub fn into_value(self) -> Value {
+        Value::from_fields(self.functions)
+    }
+}
@@ -0,0 +1,198 @@
+use crate::diagnostic::{Diagnostic, Source};
+use crate::parser::{finish, fragments, IResult};
+use crate::vm::Value;
+use anyhow::{anyhow, Result};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while_m_n},
+    character::complete::{char, digit0, digit1, one_of},
+    combinator::{all_consuming, cut, map, map_opt, opt, recognize, value},
+    error::context,
+    multi::many0,
+    sequence::{pair, preceded, separated_pair, terminated, tuple},
+};
+use std::fs;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Reads the JSON file at `path` as a value, with objects as blocks and arrays as lists.
+pub fn read_file(path: &str) -> Result<Value> {
+    let text = fs::read_to_string(path).map_err(|e| anyhow!("cannot read `{}`: {}", path, e))?;
+    parse(&Arc::new(Source::new(path, text))).map_err(|e| {
+        anyhow!(
+            "cannot parse `{}` as JSON: {} at {}",
+            path,
+            e.message,
+            e.location
+        )
+    })
+}
+
+pub fn parse(source: &Arc<Source>) -> Result<Value, Diagnostic> {
+    finish(
+        source,
+        all_consuming(terminated(element, space))(&source.text),
+    )
+}
+
+fn space(input: &str) -> IResult<'_, &str> {
+    take_while(|c| c == ' ' || c == '\t' || c == '\n' || c == '\r')(input)
+}
+
+fn element(input: &str) -> IResult<'_, Value> {
+    preceded(
+        space,
+        context(
+            "JSON value",
+            alt((
+                object,
+                array,
+                map(string, |s| Value::string(Rc::new(s))),
+                number,
+                value(Value::bool(true), tag("true")),
+                value(Value::bool(false), tag("false")),
+                value(Value::null(), tag("null")),
+            )),
+        ),
+    )(input)
+}
+
+/// Items separated by commas up to the `close` delimiter.
+fn items<'a, O, F>(item: F, close: char) -> impl Fn(&'a str) -> IResult<'a, Vec<O>>
+where
+    F: Fn(&'a str) -> IResult<'a, O> + Copy,
+{
+    move |input| {
+        let close = move |input| preceded(space, char(close))(input);
+        alt((
+            map(close, |_| Vec::new()),
+            terminated(
+                map(
+                    pair(item, many0(preceded(preceded(space, char(',')), cut(item)))),
+                    |(first, mut rest)| {
+                        rest.insert(0, first);
+                        rest
+                    },
+                ),
+                cut(close),
+            ),
+        ))(input)
+    }
+}
+
+fn member(input: &str) -> IResult<'_, (String, Value)> {
+    separated_pair(
+        preceded(space, context("string", string)),
+        cut(preceded(space, char(':'))),
+        cut(element),
+    )(input)
+}
+
+fn object(input: &str) -> IResult<'_, Value> {
+    map(
+        preceded(char('{'), cut(items(member, '}'))),
+        Value::from_fields,
+    )(input)
+}
+
+fn array(input: &str) -> IResult<'_, Value> {
+    map(preceded(char('['), cut(items(element, ']'))), |values| {
+        Value::list(Rc::new(values))
+    })(input)
+}
+
+fn number(input: &str) -> IResult<'_, Value> {
+    let (input, n) = recognize(tuple((
+        opt(char('-')),
+        alt((tag("0"), recognize(pair(one_of("123456789"), digit0)))),
+        opt(pair(char('.'), cut(context("digit", digit1)))),
+        opt(tuple((
+            one_of("eE"),
+            opt(one_of("+-")),
+            cut(context("digit", digit1)),
+        ))),
+    )))(input)?;
+    Ok((input, Value::number(f64::from_str(n).unwrap())))
+}
+
+fn hex4(input: &str) -> IResult<'_, u32> {
+    map_opt(
+        take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit()),
+        |hex| u32::from_str_radix(hex, 16).ok(),
+    )(input)
+}
+
+/// `\uXXXX`, where characters beyond the basic plane take a pair of surrogates.
+fn unicode(input: &str) -> IResult<'_, char> {
+    let code = |input| preceded(char('u'), hex4)(input);
+    alt((
+        map_opt(
+            separated_pair(code, char('\\'), code),
+            |(high, low)| match (high, low) {
+                (0xD800..=0xDBFF, 0xDC00..=0xDFFF) => {
+                    char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+                }
+                _ => None,
+            },
+        ),
+        map_opt(code, char::from_u32),
+    ))(input)
+}
+
+fn escape(input: &str) -> IResult<'_, char> {
+    preceded(
+        char('\\'),
+        cut(context(
+            "escape sequence",
+            alt((
+                value('"', char('"')),
+                value('\\', char('\\')),
+                value('/', char('/')),
+                value('\u{8}', char('b')),
+                value('\u{c}', char('f')),
+                value('\n', char('n')),
+                value('\r', char('r')),
+                value('\t', char('t')),
+                unicode,
+            )),
+        )),
+    )(input)
+}
+
+fn string(input: &str) -> IResult<'_, String> {
+    preceded(
+        char('"'),
+        cut(terminated(fragments(escape, "\"\\"), char('"'))),
+    )(input)
+}
+
+#[test]
+fn test_parse() {
+    let parse = |text: &str| parse(&Arc::new(Source::new("test.json", text)));
+    let cases = vec![
+        ("null", "null"),
+        (" [1, -2.5e2, 0.125, true] ", "[1, -250, 0.125, true]"),
+        (r#""a\"\u00e9\ud83d\ude00\n""#, r#""a\"é😀\n""#),
+        (
+            r#"{"name": "web", "ports": [80, 443], "tls": {}}"#,
+            r#"{name: "web", ports: [80, 443], tls: {}}"#,
+        ),
+        (r#"{"a": 1, "b": 2, "a": 3}"#, "{a: 3, b: 2}"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(parse(text).unwrap().to_string(), expected, "{}", text);
+    }
+
+    let errors = vec![
+        ("[1, 2", "expected `]`, found end of input"),
+        ("{\"a\" 1}", "expected `:`, found `1`"),
+        ("{a: 1}", "expected `}` or string, found `a`"),
+        ("01", "unexpected `1`"),
+        ("\"\\x\"", "expected escape sequence, found `x`"),
+        ("[1,]", "expected JSON value, found `]`"),
+    ];
+    for (text, expected) in errors {
+        assert_eq!(parse(text).unwrap_err().message, expected, "{}", text);
+    }
+}
@@ -0,0 +1,28 @@
+//! Evaluates spctr, a language for writing configuration, from Rust.
+//!
+//! ```no_run
+//! let engine = spctr::Engine::new();
+//! let config = engine.eval_file("app.spc")?;
+//! let port = config.get("port").and_then(|port| port.as_number());
+//! # Ok::<(), spctr::Error>(())
+//! ```
+
+pub mod diagnostic;
+mod engine;
+mod host;
+mod json;
+mod module;
+pub mod output;
+mod parser;
+#[cfg(feature = "serde")]
+mod serde;
+mod stdlib;
+mod token;
+mod translator;
+mod vm;
+
+pub use crate::engine::{Engine, Error, Session};
+pub use crate::host::{Caller, FromValue, HostFunction, HostModule};
+#[cfg(feature = "serde")]
+pub use crate::serde::{to_value, Error as SerdeError};
+pub use crate::vm::{Error as RuntimeError, Frame, Value};
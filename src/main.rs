@@ -1,38 +1,87 @@
-mod lib;
-mod parser;
-mod token;
-mod translator;
-mod vm;
+mod repl;
 
-use crate::vm::Value;
 use anyhow::{anyhow, Result};
 use clap::{App, Arg};
+use spctr::{output, Engine, Error, Value};
 
 use std::fs;
+use std::io::{self, Read};
+use std::rc::Rc;
 
 fn main() -> Result<()> {
     let matches = App::new("spctr")
         .arg(Arg::with_name("FILE").index(1))
         .arg(Arg::with_name("input").short("c").takes_value(true))
-        .arg(Arg::with_name("use_stdin").short("i").takes_value(false))
+        .arg(
+            Arg::with_name("use_stdin")
+                .short("i")
+                .takes_value(false)
+                .conflicts_with_all(&["FILE", "input"])
+                .help("Reads the program from stdin"),
+        )
+        .arg(
+            Arg::with_name("raw_input")
+                .short("R")
+                .long("raw-input")
+                .takes_value(false)
+                .conflicts_with("use_stdin")
+                .help("Binds the data piped to stdin to `stdin` as a string"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&["json", "yaml", "toml"])
+                .help("Writes the result in the given format instead of spctr syntax"),
+        )
+        .arg(
+            Arg::with_name("library")
+                .short("L")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Searches the directory for imports before those in SPCTR_PATH"),
+        )
         .get_matches();
 
-    let input = match matches.value_of("input") {
-        Some(v) => v.to_string(),
-        None => {
-            let path = matches.value_of("FILE").unwrap();
-            fs::read_to_string(path)?
+    let mut engine = Engine::new();
+    for dir in matches.values_of("library").into_iter().flatten() {
+        engine.add_library_dir(dir);
+    }
+
+    let (name, text) = match (matches.value_of("input"), matches.value_of("FILE")) {
+        _ if matches.is_present("use_stdin") => ("<stdin>", read_stdin()?),
+        (Some(v), _) => ("<command line>", v.to_string()),
+        (None, Some(path)) => {
+            let text = fs::read_to_string(path)
+                // The message already includes the OS error, so it is not kept as the cause.
+                .map_err(|e| anyhow!("{}", Error::Io(path.into(), e)))?;
+            (path, text)
+        }
+        (None, None) if matches.is_present("raw_input") => {
+            return Err(anyhow!("-R needs a program given by FILE or -c"))
         }
+        (None, None) => return repl::start(&engine),
     };
 
-    println!("{}", eval(&input)?);
+    if matches.is_present("raw_input") {
+        let data = read_stdin()?;
+        engine.bind("stdin", Value::string(Rc::new(data)));
+    }
+
+    let value = engine.eval_source(name, &text)?;
+    match matches.value_of("output") {
+        Some("json") => println!("{}", output::json(&value)?),
+        Some("yaml") => println!("{}", output::yaml(&value)?),
+        Some("toml") => println!("{}", output::toml(&value)?),
+        _ => println!("{}", value),
+    }
     Ok(())
 }
 
-fn eval(input: &str) -> Result<Value> {
-    let token = parser::parse(&input)
-        .map_err(|s| anyhow!("Parsing failed!, {}", s))?
-        .1;
-    let cmd = translator::get_cmd(&token);
-    vm::run(&cmd)
+fn read_stdin() -> Result<String> {
+    let mut buf = String::new();
+    io::stdin().read_to_string(&mut buf)?;
+    Ok(buf)
 }
@@ -0,0 +1,379 @@
+use crate::diagnostic::{Diagnostic, Location, Source};
+use crate::json;
+use crate::parser;
+use crate::token::*;
+use crate::vm::Value;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// The standard library, which is built in and organized like the libraries on the library path.
+const STDLIB_MANIFEST: &str = include_str!("stdlib/prelude.manifest");
+const STDLIB: &[(&str, &str)] = &[("iterator.spc", include_str!("stdlib/iterator.spc"))];
+
+/// The file listing the modules of a library which are bound in the prelude.
+const MANIFEST: &str = "prelude.manifest";
+
+/// Directories searched in order for imports of bare paths such as `import "util"`.
+#[derive(Clone, Debug, Default)]
+pub struct Library {
+    pub dirs: Vec<PathBuf>,
+}
+
+impl Library {
+    /// `dirs` followed by the directories listed in `SPCTR_PATH`.
+    pub fn new(mut dirs: Vec<PathBuf>) -> Library {
+        if let Some(paths) = env::var_os("SPCTR_PATH") {
+            dirs.extend(env::split_paths(&paths).filter(|dir| !dir.as_os_str().is_empty()));
+        }
+        Library { dirs }
+    }
+
+    /// Resolves `path` imported from `importer`, relative to the importer's directory.
+    /// Relative paths which are not found there, unless they start with `./` or `../`,
+    /// are looked up in the library directories, with `.spc` added if they have no extension.
+    fn resolve(&self, importer: &Source, path: &str) -> io::Result<PathBuf> {
+        let path = Path::new(path);
+        let dir = Path::new(&importer.name)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        let local = normalize(&dir.join(path));
+        if path.is_absolute() || path.starts_with(".") || path.starts_with("..") || local.is_file()
+        {
+            return Ok(local);
+        }
+        let file = match path.extension() {
+            Some(_) => path.to_path_buf(),
+            None => path.with_extension("spc"),
+        };
+        self.dirs
+            .iter()
+            .map(|dir| normalize(&dir.join(&file)))
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "no `{}` next to the importer, and no `{}` in the library path",
+                        path.display(),
+                        file.display()
+                    ),
+                )
+            })
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// A module bound under `name` in the scope of the prelude.
+#[derive(Clone)]
+pub struct Module {
+    pub name: String,
+    pub source: Arc<Source>,
+    pub content: Content,
+}
+
+#[derive(Clone)]
+pub enum Content {
+    Spctr(Ast),
+    Json(Value),
+    /// Another name for the module bound under the given name.
+    Alias(String),
+}
+
+/// The modules to bind for a program, and the names of the modules each import refers to,
+/// keyed by the name of the importing source and the path as written.
+pub struct Modules {
+    pub modules: Vec<Module>,
+    pub imports: HashMap<(String, String), String>,
+}
+
+/// Parses the lines of a manifest, `Name = path`, skipping blank lines and `#` comments.
+fn parse_manifest(source: &Arc<Source>) -> Result<Vec<(String, String, Location)>, Diagnostic> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    for line in source.text.split('\n') {
+        let location = Location::new(source.clone(), offset, offset + line.len());
+        offset += line.len() + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line.split_once('=').and_then(|(name, path)| {
+            let (name, path) = (name.trim(), path.trim());
+            let is_identifier =
+                !name.is_empty() && name.chars().all(|c| c.is_alphabetic() || c == '_');
+            if is_identifier && !path.is_empty() {
+                Some((name.to_string(), path.to_string(), location.clone()))
+            } else {
+                None
+            }
+        });
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => {
+                return Err(Diagnostic::new(
+                    "expected a module as `Name = path`",
+                    location,
+                ))
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads and parses modules for a program, or for the entries of a session, loading each module once
+/// however many times it is imported, from the prelude or from the program.
+#[derive(Clone)]
+pub struct Loader {
+    library: Library,
+    modules: Vec<Module>,
+    imports: HashMap<(String, String), String>,
+    loaded: HashSet<String>,
+    /// The modules being loaded, by their names, paths and the imports which led to them.
+    stack: Vec<(String, String, Option<Location>)>,
+}
+
+impl Loader {
+    pub fn new(library: Library) -> Loader {
+        Loader {
+            library,
+            modules: Vec::new(),
+            imports: HashMap::new(),
+            loaded: HashSet::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Reads and parses the modules of the prelude, listed by the manifests of the standard library
+    /// and of the libraries on the library path.
+    pub fn load_prelude(&mut self) -> Result<Modules, Diagnostic> {
+        let manifest = Arc::new(Source::new("stdlib/prelude.manifest", STDLIB_MANIFEST));
+        for (name, path, _) in parse_manifest(&manifest)? {
+            let text = STDLIB
+                .iter()
+                .find(|(file, _)| *file == path)
+                .map(|(_, text)| text)
+                .expect("the modules of the standard library are built in");
+            let source = Arc::new(Source::new(format!("stdlib/{}", path), text));
+            let ast = parser::parse(&source)?;
+            self.modules.push(Module {
+                name,
+                source,
+                content: Content::Spctr(ast),
+            });
+        }
+
+        for dir in self.library.dirs.clone() {
+            let path = dir.join(MANIFEST);
+            if !path.is_file() {
+                continue;
+            }
+            let text = fs::read_to_string(&path).map_err(|e| {
+                let source = Arc::new(Source::new(path.display(), ""));
+                Diagnostic::new(
+                    format!("cannot read the manifest: {}", e),
+                    Location::new(source, 0, 0),
+                )
+            })?;
+            let manifest = Arc::new(Source::new(path.display(), text));
+            for (name, path, location) in parse_manifest(&manifest)? {
+                let bind = self.load_file(normalize(&dir.join(path)), location)?;
+                self.modules.push(Module {
+                    name,
+                    source: manifest.clone(),
+                    content: Content::Alias(bind),
+                });
+            }
+        }
+        Ok(self.take())
+    }
+
+    /// Reads and parses every module imported by `definitions` and `body` of `source`, and the modules they import in turn,
+    /// returning those which were not loaded before.
+    pub fn load(
+        &mut self,
+        source: &Arc<Source>,
+        definitions: &[Bind],
+        body: Option<&Expression>,
+    ) -> Result<Modules, Diagnostic> {
+        let mut imports = Vec::new();
+        for (_, expression) in definitions {
+            expression_imports(expression, &mut imports);
+        }
+        if let Some(body) = body {
+            expression_imports(body, &mut imports);
+        }
+
+        let depth = self.stack.len();
+        // Lets a module importing the program itself be reported as a cycle.
+        if let Ok(canonical) = fs::canonicalize(&source.name) {
+            let name = format!("import {}", canonical.display());
+            self.stack.push((name, source.name.clone(), None));
+        }
+        let result = self.load_imports(source, imports);
+        self.stack.truncate(depth);
+        result?;
+        Ok(self.take())
+    }
+
+    /// The modules loaded since the last call, along with the imports referring to them.
+    fn take(&mut self) -> Modules {
+        Modules {
+            modules: mem::take(&mut self.modules),
+            imports: mem::take(&mut self.imports),
+        }
+    }
+
+    fn load_imports(
+        &mut self,
+        source: &Arc<Source>,
+        imports: Vec<(&String, Span)>,
+    ) -> Result<(), Diagnostic> {
+        for (path, span) in imports {
+            let (start, end) = span.range(source.text.len());
+            let location = Location::new(source.clone(), start, end);
+            let resolved = self
+                .library
+                .resolve(source, path)
+                .map_err(|e| self.error(format!("cannot import `{}`: {}", path, e), &location))?;
+            let name = self.load_file(resolved, location)?;
+            self.imports
+                .insert((source.name.clone(), path.clone()), name);
+        }
+        Ok(())
+    }
+
+    /// Loads the module at `path` unless it is loaded already, and returns the name it is bound under.
+    fn load_file(&mut self, path: PathBuf, location: Location) -> Result<String, Diagnostic> {
+        let error = |loader: &Loader, e: io::Error| {
+            loader.error(
+                format!("cannot import `{}`: {}", path.display(), e),
+                &location,
+            )
+        };
+        let canonical = fs::canonicalize(&path).map_err(|e| error(self, e))?;
+        let name = format!("import {}", canonical.display());
+        if let Some(i) = self.stack.iter().position(|(n, _, _)| *n == name) {
+            let mut cycle: Vec<_> = self.stack[i..]
+                .iter()
+                .map(|(_, path, _)| format!("`{}`", path))
+                .collect();
+            cycle.push(cycle[0].clone());
+            let message = format!("import cycle: {}", cycle.join(" -> "));
+            return Err(self.error(message, &location));
+        }
+        if !self.loaded.insert(name.clone()) {
+            return Ok(name);
+        }
+
+        let text = fs::read_to_string(&path).map_err(|e| error(self, e))?;
+        let source = Arc::new(Source::new(path.display(), text));
+        let is_json = path.extension() == Some("json".as_ref());
+
+        self.stack
+            .push((name.clone(), source.name.clone(), Some(location)));
+        let content = if is_json {
+            let value = json::parse(&source).map_err(|e| self.noted(e))?;
+            Content::Json(value)
+        } else {
+            let ast = parser::parse(&source).map_err(|e| self.noted(e))?;
+            let mut imports = Vec::new();
+            statement_imports(&ast, &mut imports);
+            self.load_imports(&source, imports)?;
+            Content::Spctr(ast)
+        };
+        self.stack.pop();
+
+        self.modules.push(Module {
+            name: name.clone(),
+            source,
+            content,
+        });
+        Ok(name)
+    }
+
+    fn error(&self, message: String, location: &Location) -> Diagnostic {
+        self.noted(Diagnostic::new(message, location.clone()))
+    }
+
+    /// Adds the chain of imports leading to the module being loaded.
+    fn noted(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        for (_, _, location) in self.stack.iter().rev() {
+            if let Some(location) = location {
+                diagnostic = diagnostic.with_note(format!("note: imported at {}", location));
+            }
+        }
+        diagnostic
+    }
+}
+
+fn statement_imports<'a>(statement: &'a Statement, imports: &mut Vec<(&'a String, Span)>) {
+    for (_, expression) in &statement.definitions {
+        expression_imports(expression, imports);
+    }
+    expression_imports(&statement.body, imports);
+}
+
+fn expression_imports<'a>(expression: &'a Expression, imports: &mut Vec<(&'a String, Span)>) {
+    match expression {
+        Expression::Binary { left, right, .. } => {
+            expression_imports(left, imports);
+            expression_imports(right, imports);
+        }
+        Expression::Unary { operand, .. } => expression_imports(operand, imports),
+        Expression::Operation(operation) => {
+            primary_imports(&operation.left, imports);
+            for (right, _) in &operation.rights {
+                match right {
+                    OperationRight::Access(_) => {}
+                    OperationRight::Call(args) => {
+                        for arg in args {
+                            expression_imports(arg, imports);
+                        }
+                    }
+                    OperationRight::Index(index) => expression_imports(index, imports),
+                }
+            }
+        }
+        Expression::If {
+            cond, cons, alt, ..
+        } => {
+            expression_imports(cond, imports);
+            expression_imports(cons, imports);
+            expression_imports(alt, imports);
+        }
+    }
+}
+
+fn primary_imports<'a>(primary: &'a Primary, imports: &mut Vec<(&'a String, Span)>) {
+    match primary {
+        Primary::Import(path, span) => imports.push((path, *span)),
+        Primary::ImmediateBlock(statement) => statement_imports(statement, imports),
+        Primary::Block(definitions) => {
+            for (_, expression) in definitions {
+                expression_imports(expression, imports);
+            }
+        }
+        Primary::List(items) => {
+            for item in items {
+                expression_imports(item, imports);
+            }
+        }
+        Primary::Function(_, body) => expression_imports(body, imports),
+        Primary::Group(expression) => expression_imports(expression, imports),
+        Primary::Number(_)
+        | Primary::Bool(_)
+        | Primary::String(_)
+        | Primary::Variable(_, _)
+        | Primary::Null => {}
+    }
+}
@@ -0,0 +1,474 @@
+//! Writers of fully evaluated values in data formats.
+//! Fields of blocks are written in declaration order, as far as the format allows.
+
+use crate::vm::Value;
+use anyhow::{anyhow, Result};
+use std::fmt::{self, Write};
+
+/// Writes a fully evaluated `value` as pretty-printed JSON.
+pub fn json(value: &Value) -> Result<String> {
+    let mut out = String::new();
+    write_json(&mut out, value, &mut Path::default(), 0)?;
+    Ok(out)
+}
+
+fn write_json(out: &mut String, value: &Value, path: &mut Path, indent: usize) -> Result<()> {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => write!(out, "{}", b)?,
+        Value::Number(n) if n.is_finite() => write!(out, "{}", n)?,
+        Value::Number(n) => return Err(path.error(format!("cannot write {} as JSON", n))),
+        Value::String(s) => write_quoted(out, s),
+        Value::Function(_) => return Err(path.error("cannot write a function as JSON")),
+        Value::List(values) if values.is_empty() => out.push_str("[]"),
+        Value::List(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                out.push_str(if i > 0 { ",\n" } else { "\n" });
+                push_indent(out, indent + 1);
+                path.segments.push(Segment::Index(i));
+                write_json(out, value, path, indent + 1)?;
+                path.segments.pop();
+            }
+            out.push('\n');
+            push_indent(out, indent);
+            out.push(']');
+        }
+        Value::Block(_) => {
+            let fields = path.enter_block(value, "JSON")?;
+            if fields.is_empty() {
+                out.push_str("{}");
+            } else {
+                out.push('{');
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    out.push_str(if i > 0 { ",\n" } else { "\n" });
+                    push_indent(out, indent + 1);
+                    write_quoted(out, name);
+                    out.push_str(": ");
+                    path.segments.push(Segment::Field(name.clone()));
+                    write_json(out, value, path, indent + 1)?;
+                    path.segments.pop();
+                }
+                out.push('\n');
+                push_indent(out, indent);
+                out.push('}');
+            }
+            path.blocks.pop();
+        }
+    }
+    Ok(())
+}
+
+/// Writes `s` as a double-quoted string, which reads the same in JSON, YAML and TOML.
+fn write_quoted(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let mut buf = [0; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    out.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Writes a fully evaluated `value` as a YAML document in block style.
+pub fn yaml(value: &Value) -> Result<String> {
+    let mut out = String::new();
+    write_yaml(&mut out, value, &mut Path::default(), 0)?;
+    Ok(out)
+}
+
+/// Writes `value` from the current position of the line, indenting the lines after it by `indent`.
+fn write_yaml(out: &mut String, value: &Value, path: &mut Path, indent: usize) -> Result<()> {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => write!(out, "{}", b)?,
+        Value::Number(n) if n.is_nan() => out.push_str(".nan"),
+        Value::Number(n) if n.is_infinite() => {
+            out.push_str(if *n > 0.0 { ".inf" } else { "-.inf" })
+        }
+        Value::Number(n) => write!(out, "{}", n)?,
+        Value::String(s) => write_yaml_string(out, s),
+        Value::Function(_) => return Err(path.error("cannot write a function as YAML")),
+        Value::List(values) if values.is_empty() => out.push_str("[]"),
+        Value::List(values) => {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    push_spaces(out, indent);
+                }
+                out.push_str("- ");
+                path.segments.push(Segment::Index(i));
+                write_yaml(out, value, path, indent + 2)?;
+                path.segments.pop();
+            }
+        }
+        Value::Block(_) => {
+            let fields = path.enter_block(value, "YAML")?;
+            if fields.is_empty() {
+                out.push_str("{}");
+            }
+            for (i, (name, value)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                    push_spaces(out, indent);
+                }
+                write_yaml_string(out, name);
+                out.push(':');
+                path.segments.push(Segment::Field(name.clone()));
+                match value {
+                    Value::List(v) if !v.is_empty() => {
+                        out.push('\n');
+                        push_spaces(out, indent + 2);
+                        write_yaml(out, value, path, indent + 2)?;
+                    }
+                    Value::Block((map, _)) if !map.is_empty() => {
+                        out.push('\n');
+                        push_spaces(out, indent + 2);
+                        write_yaml(out, value, path, indent + 2)?;
+                    }
+                    _ => {
+                        out.push(' ');
+                        write_yaml(out, value, path, indent)?;
+                    }
+                }
+                path.segments.pop();
+            }
+            path.blocks.pop();
+        }
+    }
+    Ok(())
+}
+
+/// Writes `s` unquoted when it cannot be read back as anything but the same string.
+fn write_yaml_string(out: &mut String, s: &str) {
+    const RESERVED: &[&str] = &["null", "true", "false", "yes", "no", "on", "off", "y", "n"];
+    let plain = s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '/')
+        && !s.ends_with(' ')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./ ".contains(c))
+        && !RESERVED.contains(&s.to_ascii_lowercase().as_str());
+    if plain {
+        out.push_str(s);
+    } else {
+        write_quoted(out, s);
+    }
+}
+
+/// Writes a fully evaluated `value`, which must be a block, as a TOML document.
+/// Fields holding blocks, or lists of blocks, become tables written after the other fields.
+pub fn toml(value: &Value) -> Result<String> {
+    if !matches!(value, Value::Block(_)) {
+        return Err(anyhow!(
+            "cannot write a {} as TOML, which needs a block at the top",
+            value.type_name()
+        ));
+    }
+    let mut out = String::new();
+    write_toml_table(&mut out, value, &mut Path::default(), &mut Vec::new())?;
+    out.pop();
+    Ok(out)
+}
+
+/// Writes the fields of `block`, whose table is named by the keys in `header`.
+fn write_toml_table(
+    out: &mut String,
+    block: &Value,
+    path: &mut Path,
+    header: &mut Vec<String>,
+) -> Result<()> {
+    let fields = path.enter_block(block, "TOML")?;
+    let mut tables = Vec::new();
+    for (name, value) in &fields {
+        match value {
+            Value::Block(_) => tables.push((name, value, false)),
+            Value::List(v) if !v.is_empty() && v.iter().all(|v| matches!(v, Value::Block(_))) => {
+                tables.push((name, value, true))
+            }
+            _ => {
+                write_toml_key(out, name);
+                out.push_str(" = ");
+                path.segments.push(Segment::Field(name.clone()));
+                write_toml_inline(out, value, path)?;
+                path.segments.pop();
+                out.push('\n');
+            }
+        }
+    }
+
+    for (name, value, is_array) in tables {
+        let mut key = String::new();
+        write_toml_key(&mut key, name);
+        header.push(key);
+        path.segments.push(Segment::Field(name.clone()));
+        match value {
+            Value::List(values) if is_array => {
+                for (i, value) in values.iter().enumerate() {
+                    write_toml_header(out, header, "[[", "]]");
+                    path.segments.push(Segment::Index(i));
+                    write_toml_table(out, value, path, header)?;
+                    path.segments.pop();
+                }
+            }
+            _ => {
+                write_toml_header(out, header, "[", "]");
+                write_toml_table(out, value, path, header)?;
+            }
+        }
+        path.segments.pop();
+        header.pop();
+    }
+    path.blocks.pop();
+    Ok(())
+}
+
+fn write_toml_header(out: &mut String, header: &[String], open: &str, close: &str) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(open);
+    out.push_str(&header.join("."));
+    out.push_str(close);
+    out.push('\n');
+}
+
+fn write_toml_key(out: &mut String, name: &str) {
+    let bare = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        out.push_str(name);
+    } else {
+        write_quoted(out, name);
+    }
+}
+
+fn write_toml_inline(out: &mut String, value: &Value, path: &mut Path) -> Result<()> {
+    match value {
+        Value::Null => return Err(path.error("cannot write null as TOML, which has no null")),
+        Value::Bool(b) => write!(out, "{}", b)?,
+        Value::Number(n) if n.is_nan() => out.push_str("nan"),
+        Value::Number(n) if n.is_infinite() => out.push_str(if *n > 0.0 { "inf" } else { "-inf" }),
+        // Integral numbers are written as integers unless they are out of the range of TOML's.
+        Value::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+            write!(out, "{}", *n as i64)?
+        }
+        Value::Number(n) if n.fract() == 0.0 => write!(out, "{}.0", n)?,
+        Value::Number(n) => write!(out, "{}", n)?,
+        Value::String(s) => write_quoted(out, s),
+        Value::Function(_) => return Err(path.error("cannot write a function as TOML")),
+        Value::List(values) => {
+            if let Some(first) = values.first() {
+                if let Some(other) = values.iter().find(|v| v.type_name() != first.type_name()) {
+                    return Err(path.error(format!(
+                        "cannot write a list of both {} and {} as TOML, whose arrays hold values of one type",
+                        first.type_name(),
+                        other.type_name()
+                    )));
+                }
+            }
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                path.segments.push(Segment::Index(i));
+                write_toml_inline(out, value, path)?;
+                path.segments.pop();
+            }
+            out.push(']');
+        }
+        Value::Block(_) => {
+            let fields = path.enter_block(value, "TOML")?;
+            out.push('{');
+            for (i, (name, value)) in fields.iter().enumerate() {
+                out.push_str(if i > 0 { ", " } else { " " });
+                write_toml_key(out, name);
+                out.push_str(" = ");
+                path.segments.push(Segment::Field(name.clone()));
+                write_toml_inline(out, value, path)?;
+                path.segments.pop();
+            }
+            out.push_str(if fields.is_empty() { "}" } else { " }" });
+            path.blocks.pop();
+        }
+    }
+    Ok(())
+}
+
+fn push_indent(out: &mut String, indent: usize) {
+    push_spaces(out, indent * 2);
+}
+
+fn push_spaces(out: &mut String, n: usize) {
+    for _ in 0..n {
+        out.push(' ');
+    }
+}
+
+enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+/// Where an emitter is within the value, to point errors at the offending part.
+#[derive(Default)]
+struct Path {
+    segments: Vec<Segment>,
+    /// The blocks being written, to detect blocks which contain themselves.
+    blocks: Vec<Value>,
+}
+
+impl Path {
+    fn error<T: fmt::Display>(&self, message: T) -> anyhow::Error {
+        if self.segments.is_empty() {
+            anyhow!("{}", message)
+        } else {
+            anyhow!("{} (at `{}`)", message, self)
+        }
+    }
+
+    /// Returns the fields of `block`, which must be left with `blocks.pop()` when written.
+    fn enter_block(&mut self, block: &Value, format: &str) -> Result<Vec<(String, Value)>> {
+        if self.blocks.iter().any(|b| b.is(block)) {
+            return Err(self.error(format!(
+                "cannot write a block which contains itself as {}",
+                format
+            )));
+        }
+        self.blocks.push(block.clone());
+        block.clone().into_fields()
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Field(name) if i == 0 => write!(f, "{}", name)?,
+                Segment::Field(name) => write!(f, ".{}", name)?,
+                Segment::Index(n) => write!(f, "[{}]", n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_json() {
+    use crate::translator::eval;
+
+    let cases = vec![
+        ("null", "null"),
+        ("[1.5, -2, true]", "[\n  1.5,\n  -2,\n  true\n]"),
+        ("\"a\\\"\\n\\u{1}\"", "\"a\\\"\\n\\u0001\""),
+        ("{}", "{}"),
+        ("[[], {}]", "[\n  [],\n  {}\n]"),
+        (
+            "{name: \"web\", ports: [80, 443], tls: {on: ports[1] = 443}}",
+            "{\n  \"name\": \"web\",\n  \"ports\": [\n    80,\n    443\n  ],\n  \"tls\": {\n    \"on\": true\n  }\n}",
+        ),
+        ("{z: 1, a: 2}", "{\n  \"z\": 1,\n  \"a\": 2\n}"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(json(&eval(text).unwrap()).unwrap(), expected, "{}", text);
+    }
+
+    let errors = vec![
+        ("(x) => x", "cannot write a function as JSON"),
+        (
+            "{a: [1, {f: (x) => x}]}",
+            "cannot write a function as JSON (at `a[1].f`)",
+        ),
+        ("{n: 0 / 0}", "cannot write NaN as JSON (at `n`)"),
+        (
+            "a: {me: a}, a",
+            "cannot write a block which contains itself as JSON (at `me`)",
+        ),
+    ];
+    for (text, expected) in errors {
+        let e = json(&eval(text).unwrap()).unwrap_err();
+        assert_eq!(e.to_string(), expected, "{}", text);
+    }
+}
+
+#[test]
+fn test_yaml() {
+    use crate::translator::eval;
+
+    let cases = vec![
+        ("null", "null"),
+        ("[]", "[]"),
+        (
+            "[1, [2, 3], {a: 1, b: 2}]",
+            "- 1\n- - 2\n  - 3\n- a: 1\n  b: 2",
+        ),
+        (
+            "{name: \"web app\", on: \"yes\", v: \"1.0\", s: \"\", e: {}}",
+            "name: web app\n\"on\": \"yes\"\nv: \"1.0\"\ns: \"\"\ne: {}",
+        ),
+        (
+            "{spec: {ports: [{port: 80}, {port: 443}], x: 0 / 0}}",
+            "spec:\n  ports:\n    - port: 80\n    - port: 443\n  x: .nan",
+        ),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(yaml(&eval(text).unwrap()).unwrap(), expected, "{}", text);
+    }
+
+    let e = yaml(&eval("{a: [(x) => x]}").unwrap()).unwrap_err();
+    assert_eq!(e.to_string(), "cannot write a function as YAML (at `a[0]`)");
+}
+
+#[test]
+fn test_toml() {
+    use crate::translator::eval;
+
+    let cases = vec![
+        ("{}", ""),
+        (
+            "{name: \"web\", port: 8080, ratio: 0.5, big: 1e20, tags: [\"a\", \"b\"], p: {x: [1, 2]}}",
+            "name = \"web\"\nport = 8080\nratio = 0.5\nbig = 100000000000000000000.0\ntags = [\"a\", \"b\"]\n\n[p]\nx = [1, 2]",
+        ),
+        (
+            "{db: {host: \"h\", pool: {size: 4}}, title: \"t\", servers: [{n: 1}, {n: 2}]}",
+            "title = \"t\"\n\n[db]\nhost = \"h\"\n\n[db.pool]\nsize = 4\n\n[[servers]]\nn = 1\n\n[[servers]]\nn = 2",
+        ),
+        ("{a: [[1], [\"x\"]], e: []}", "a = [[1], [\"x\"]]\ne = []"),
+        ("{a: [[{x: 1, y: {}}]]}", "a = [[{ x = 1, y = {} }]]"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(toml(&eval(text).unwrap()).unwrap(), expected, "{}", text);
+    }
+
+    let errors = vec![
+        ("[1]", "cannot write a list as TOML, which needs a block at the top"),
+        (
+            "{a: {b: null}}",
+            "cannot write null as TOML, which has no null (at `a.b`)",
+        ),
+        (
+            "{a: [1, \"x\"]}",
+            "cannot write a list of both number and string as TOML, whose arrays hold values of one type (at `a`)",
+        ),
+        (
+            "{a: [{f: (x) => x}]}",
+            "cannot write a function as TOML (at `a[0].f`)",
+        ),
+    ];
+    for (text, expected) in errors {
+        let e = toml(&eval(text).unwrap()).unwrap_err();
+        assert_eq!(e.to_string(), expected, "{}", text);
+    }
+}
@@ -1,222 +1,646 @@
+use crate::diagnostic::{Diagnostic, Location, Source};
 use crate::token::*;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until, take_while1},
-    character::complete::{char, digit1, multispace0},
-    combinator::{all_consuming, map, opt},
+    bytes::complete::{is_not, tag, take_while, take_while1, take_while_m_n},
+    character::complete::{char, digit1, hex_digit1, line_ending, multispace1, one_of, space0},
+    combinator::{all_consuming, cut, map, map_opt, not, opt, peek, recognize, value},
+    error::{context, ErrorKind, ParseError},
     multi::{fold_many0, many0, separated_list},
-    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
-    IResult,
+    sequence::{delimited, pair, preceded, terminated, tuple},
 };
+use std::cmp::Ordering;
+use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
-fn number(input: &str) -> IResult<&str, Primary> {
-    let (input, n) = map(pair(opt(char('-')), digit1), |(neg, v)| {
-        let n: f64 = FromStr::from_str(v).unwrap();
-        if neg.is_some() {
-            return -n;
+pub(crate) type IResult<'a, O> = nom::IResult<&'a str, O, Error<'a>>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expected {
+    Char(char),
+    Label(&'static str),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Char(c) => write!(f, "`{}`", c),
+            Expected::Label(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+/// Parse error which keeps the furthest position reached along with what could have continued there.
+#[derive(Debug, PartialEq)]
+pub struct Error<'a> {
+    input: &'a str,
+    expected: Vec<Expected>,
+}
+
+impl<'a> ParseError<&'a str> for Error<'a> {
+    fn from_error_kind(input: &'a str, _: ErrorKind) -> Self {
+        Error {
+            input,
+            expected: Vec::new(),
         }
-        n
-    })(input)?;
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn from_char(input: &'a str, c: char) -> Self {
+        Error {
+            input,
+            expected: vec![Expected::Char(c)],
+        }
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        match self.input.len().cmp(&other.input.len()) {
+            Ordering::Less => self,
+            Ordering::Greater => other,
+            Ordering::Equal => {
+                for e in other.expected {
+                    if !self.expected.contains(&e) {
+                        self.expected.push(e);
+                    }
+                }
+                self
+            }
+        }
+    }
+
+    fn add_context(input: &'a str, ctx: &'static str, other: Self) -> Self {
+        // A failure right at the start of the context is better described by the context itself.
+        if skip_space(input).len() == other.input.len() {
+            return Error {
+                input: other.input,
+                expected: vec![Expected::Label(ctx)],
+            };
+        }
+        other
+    }
+}
+
+impl<'a> Error<'a> {
+    fn into_diagnostic(self, source: &Arc<Source>) -> Diagnostic {
+        let rest = skip_space(self.input);
+        let (offset, found) = match rest.chars().next() {
+            Some(c) => (source.text.len() - rest.len(), format!("`{}`", c)),
+            None => (source.text.trim_end().len(), "end of input".to_string()),
+        };
+
+        let message = match self.expected.split_last() {
+            None => format!("unexpected {}", found),
+            Some((last, [])) => format!("expected {}, found {}", last, found),
+            Some((last, init)) => {
+                let init: Vec<_> = init.iter().map(|e| e.to_string()).collect();
+                format!("expected {} or {}, found {}", init.join(", "), last, found)
+            }
+        };
+        let len = rest.chars().next().map_or(0, |c| c.len_utf8());
+        Diagnostic::new(message, Location::new(source.clone(), offset, offset + len))
+    }
+}
+
+pub(crate) fn finish<T>(source: &Arc<Source>, result: IResult<'_, T>) -> Result<T, Diagnostic> {
+    match result {
+        Ok((_, v)) => Ok(v),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Err(e.into_diagnostic(source)),
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never ask for more input"),
+    }
+}
+
+/// Digits optionally separated by single underscores, e.g. `1_000_000`.
+fn digits<'a, F>(digit: F) -> impl Fn(&'a str) -> IResult<'a, &'a str>
+where
+    F: Fn(&'a str) -> IResult<'a, &'a str> + Copy,
+{
+    let digit = move |input| context("digit", digit)(input);
+    recognize(pair(digit, many0(preceded(char('_'), cut(digit)))))
+}
+
+fn hexadecimal(input: &str) -> IResult<'_, f64> {
+    let (input, v) = preceded(alt((tag("0x"), tag("0X"))), cut(digits(hex_digit1)))(input)?;
+    let n = v
+        .chars()
+        .filter_map(|c| c.to_digit(16))
+        .fold(0.0, |n, d| n * 16.0 + f64::from(d));
+    Ok((input, n))
+}
+
+fn decimal(input: &str) -> IResult<'_, f64> {
+    let (input, v) = recognize(tuple((
+        digits(digit1),
+        opt(pair(char('.'), digits(digit1))),
+        opt(tuple((
+            one_of("eE"),
+            opt(one_of("+-")),
+            cut(digits(digit1)),
+        ))),
+    )))(input)?;
+    let v: String = v.chars().filter(|c| *c != '_').collect();
+    Ok((input, f64::from_str(&v).unwrap()))
+}
+
+fn number(input: &str) -> IResult<'_, Primary> {
+    let (input, n) = map(
+        pair(opt(char('-')), alt((hexadecimal, decimal))),
+        |(neg, n)| {
+            if neg.is_some() {
+                return -n;
+            }
+            n
+        },
+    )(input)?;
     Ok((input, Primary::Number(n)))
 }
 
-fn identifier(input: &str) -> IResult<&str, String> {
-    map(
-        take_while1(|chr: char| chr.is_alphabetic() || chr == '_'),
-        |s: &str| s.to_string(),
+fn is_identifier_char(chr: char) -> bool {
+    chr.is_alphabetic() || chr == '_'
+}
+
+fn identifier(input: &str) -> IResult<'_, String> {
+    context(
+        "identifier",
+        map(take_while1(is_identifier_char), |s: &str| s.to_string()),
     )(input)
 }
 
-fn variable(input: &str) -> IResult<&str, Primary> {
-    map(identifier, Primary::Variable)(input)
+fn keyword<'a>(word: &'static str) -> impl Fn(&'a str) -> IResult<'a, &'a str> {
+    terminated(tag(word), not(peek(take_while1(is_identifier_char))))
+}
+
+fn line_comment(input: &str) -> IResult<'_, &str> {
+    recognize(pair(tag("//"), take_while(|c| c != '\n')))(input)
+}
+
+/// `/* ... */`, which may contain other block comments.
+fn block_comment(input: &str) -> IResult<'_, &str> {
+    let (mut rest, _) = tag("/*")(input)?;
+    let mut depth = 1;
+    while depth > 0 {
+        rest = match (rest.find("/*"), rest.find("*/")) {
+            (Some(open), Some(close)) if open < close => {
+                depth += 1;
+                &rest[open + 2..]
+            }
+            (_, Some(close)) => {
+                depth -= 1;
+                &rest[close + 2..]
+            }
+            (_, None) => {
+                return Err(nom::Err::Failure(Error {
+                    input: &rest[rest.len()..],
+                    expected: vec![Expected::Label("`*/`")],
+                }))
+            }
+        };
+    }
+    Ok((rest, &input[..input.len() - rest.len()]))
 }
 
-fn immediate_block(input: &str) -> IResult<&str, Primary> {
+/// Whitespace and comments, which may appear between any tokens.
+fn space(input: &str) -> IResult<'_, &str> {
+    recognize(many0(alt((multispace1, line_comment, block_comment))))(input)
+}
+
+fn skip_space(input: &str) -> &str {
+    space(input).map_or(input, |(rest, _)| rest)
+}
+
+/// Runs `f` along with the span of what it consumed, leaving out leading whitespace and comments
+/// as well as trailing whitespace.
+fn spanned<'a, O, F>(f: F) -> impl Fn(&'a str) -> IResult<'a, (O, Span)>
+where
+    F: Fn(&'a str) -> IResult<'a, O>,
+{
+    move |input: &'a str| {
+        let (rest, o) = f(input)?;
+        let start = skip_space(input).len().max(rest.len());
+        let consumed = &input[input.len() - start..input.len() - rest.len()];
+        Ok((
+            rest,
+            (o, Span::new(start, start - consumed.trim_end().len())),
+        ))
+    }
+}
+
+fn variable(input: &str) -> IResult<'_, Primary> {
+    map(spanned(identifier), |(name, span)| {
+        Primary::Variable(name, span)
+    })(input)
+}
+
+fn import(input: &str) -> IResult<'_, Primary> {
+    map(
+        spanned(preceded(
+            keyword("import"),
+            cut(preceded(space, context("module path", string))),
+        )),
+        |(path, span)| Primary::Import(path, span),
+    )(input)
+}
+
+fn immediate_block(input: &str) -> IResult<'_, Primary> {
     let (input, s) = delimited(char('{'), statement, char('}'))(input)?;
     Ok((input, Primary::ImmediateBlock(Box::new(s))))
 }
 
-fn arrow(input: &str) -> IResult<&str, &str> {
-    delimited(multispace0, tag("=>"), multispace0)(input)
+fn arrow(input: &str) -> IResult<'_, &str> {
+    context("`=>`", delimited(space, tag("=>"), space))(input)
 }
 
-fn call(input: &str) -> IResult<&str, OperationRight> {
+fn call(input: &str) -> IResult<'_, OperationRight> {
     map(
-        delimited(char('('), separated_list(char(','), expression), char(')')),
+        preceded(
+            char('('),
+            cut(terminated(separated_list(char(','), expression), char(')'))),
+        ),
         OperationRight::Call,
     )(input)
 }
 
-fn index(input: &str) -> IResult<&str, OperationRight> {
+fn index(input: &str) -> IResult<'_, OperationRight> {
     map(
-        delimited(char('['), expression, char(']')),
+        preceded(char('['), cut(terminated(expression, char(']')))),
         OperationRight::Index,
     )(input)
 }
 
-fn args(input: &str) -> IResult<&str, Vec<String>> {
+fn args(input: &str) -> IResult<'_, Vec<String>> {
     delimited(
         char('('),
-        separated_list(char(','), delimited(multispace0, identifier, multispace0)),
+        separated_list(char(','), delimited(space, identifier, space)),
         char(')'),
     )(input)
 }
 
-fn function(input: &str) -> IResult<&str, Primary> {
-    let (input, s) = pair(args, preceded(arrow, expression))(input)?;
+fn function(input: &str) -> IResult<'_, Primary> {
+    let (input, s) = pair(args, preceded(arrow, cut(expression)))(input)?;
     Ok((input, Primary::Function(s.0, Box::new(s.1))))
 }
 
-fn string(input: &str) -> IResult<&str, String> {
-    map(
-        delimited(char('"'), take_until("\""), char('"')),
-        String::from,
+#[derive(Debug)]
+enum Fragment<'a> {
+    Literal(&'a str),
+    Escaped(char),
+}
+
+fn escape(input: &str) -> IResult<'_, char> {
+    preceded(
+        char('\\'),
+        cut(context(
+            "escape sequence",
+            alt((
+                value('\n', char('n')),
+                value('\r', char('r')),
+                value('\t', char('t')),
+                value('\0', char('0')),
+                value('\\', char('\\')),
+                value('"', char('"')),
+                map_opt(
+                    delimited(
+                        tag("u{"),
+                        take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()),
+                        char('}'),
+                    ),
+                    |hex| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                ),
+            )),
+        )),
+    )(input)
+}
+
+/// Characters up to one of `stop`, with escape sequences resolved by `escape`.
+pub(crate) fn fragments<'a, E>(
+    escape: E,
+    stop: &'static str,
+) -> impl Fn(&'a str) -> IResult<'a, String>
+where
+    E: Fn(&'a str) -> IResult<'a, char> + Copy,
+{
+    move |input| {
+        fold_many0(
+            alt((
+                map(escape, Fragment::Escaped),
+                map(is_not(stop), Fragment::Literal),
+            )),
+            String::new(),
+            |mut s, fragment| {
+                match fragment {
+                    Fragment::Literal(l) => s.push_str(l),
+                    Fragment::Escaped(c) => s.push(c),
+                }
+                s
+            },
+        )(input)
+    }
+}
+
+fn quoted_string(input: &str) -> IResult<'_, String> {
+    preceded(
+        char('"'),
+        cut(terminated(fragments(escape, "\"\\"), char('"'))),
     )(input)
 }
 
-fn string_literal(input: &str) -> IResult<&str, Primary> {
+/// `r"..."`, or `r#"..."#` with any number of `#` to allow quotes inside. Escapes are kept as written.
+fn raw_string(input: &str) -> IResult<'_, String> {
+    let (input, hashes) = delimited(char('r'), take_while(|c| c == '#'), char('"'))(input)?;
+    let close = format!("\"{}", hashes);
+    match input.find(&close) {
+        Some(i) => Ok((&input[i + close.len()..], input[..i].to_string())),
+        None => Err(nom::Err::Failure(Error {
+            input: &input[input.len()..],
+            expected: vec![Expected::Label("end of raw string")],
+        })),
+    }
+}
+
+/// Length of a text block body, which ends at the first unescaped `"""`.
+fn text_block_len(body: &str) -> Option<usize> {
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' if body[i..].starts_with("\"\"\"") => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Resolves escapes in the first `len` bytes of `input`, reporting errors at their position in `input`.
+fn unescape_line(input: &str, len: usize) -> Result<String, nom::Err<Error<'_>>> {
+    let relocate = |e: Error<'_>| Error {
+        input: &input[len - e.input.len()..],
+        expected: e.expected,
+    };
+    all_consuming(fragments(escape, "\\"))(&input[..len])
+        .map(|(_, s)| s)
+        .map_err(|e| e.map(relocate))
+}
+
+/// A text block spanning multiple lines between `"""`s.
+/// The content starts on the line after the opening `"""`,
+/// and the indentation common to its lines and the closing `"""` is stripped.
+fn text_block(input: &str) -> IResult<'_, String> {
+    let (input, _) = tag("\"\"\"")(input)?;
+    let (body, _) = cut(context("newline", preceded(space0, line_ending)))(input)?;
+    let len = text_block_len(body).ok_or_else(|| {
+        nom::Err::Failure(Error {
+            input: &body[body.len()..],
+            expected: vec![Expected::Label("`\"\"\"`")],
+        })
+    })?;
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in body[..len].split('\n') {
+        lines.push((offset, line.strip_suffix('\r').unwrap_or(line)));
+        offset += line.len() + 1;
+    }
+    // The last line holds the closing `"""`, which only marks the indentation when nothing precedes it.
+    let (_, closing) = lines[lines.len() - 1];
+    let closed_on_own_line = closing.trim().is_empty();
+    if closed_on_own_line {
+        lines.pop();
+    }
+
+    let indentation = |line: &str| line.len() - line.trim_start_matches(&[' ', '\t'][..]).len();
+    let indent = lines
+        .iter()
+        .map(|(_, line)| *line)
+        .filter(|line| !line.trim().is_empty())
+        .chain(if closed_on_own_line {
+            Some(closing)
+        } else {
+            None
+        })
+        .map(indentation)
+        .min()
+        .unwrap_or(0);
+
+    let mut text = String::new();
+    for (i, (offset, line)) in lines.iter().enumerate() {
+        if i > 0 {
+            text.push('\n');
+        }
+        if !line.trim().is_empty() {
+            text.push_str(&unescape_line(
+                &body[offset + indent..],
+                line.len() - indent,
+            )?);
+        }
+    }
+    if closed_on_own_line && !lines.is_empty() {
+        text.push('\n');
+    }
+    Ok((&body[len + 3..], text))
+}
+
+fn string(input: &str) -> IResult<'_, String> {
+    alt((text_block, raw_string, quoted_string))(input)
+}
+
+fn string_literal(input: &str) -> IResult<'_, Primary> {
     map(string, Primary::String)(input)
 }
 
-fn block(input: &str) -> IResult<&str, Primary> {
+fn block(input: &str) -> IResult<'_, Primary> {
     let (input, s) = delimited(char('{'), definitions, char('}'))(input)?;
     Ok((input, Primary::Block(s)))
 }
 
-fn list(input: &str) -> IResult<&str, Primary> {
+fn list(input: &str) -> IResult<'_, Primary> {
     map(
-        delimited(char('['), separated_list(char(','), expression), char(']')),
+        preceded(
+            char('['),
+            cut(terminated(separated_list(char(','), expression), char(']'))),
+        ),
         Primary::List,
     )(input)
 }
 
-fn null(input: &str) -> IResult<&str, Primary> {
-    map(tag("null"), |_| Primary::Null)(input)
+fn group(input: &str) -> IResult<'_, Primary> {
+    map(
+        preceded(char('('), cut(terminated(expression, char(')')))),
+        |expression| Primary::Group(Box::new(expression)),
+    )(input)
 }
 
-fn primary(input: &str) -> IResult<&str, Primary> {
+fn boolean(input: &str) -> IResult<'_, Primary> {
+    alt((
+        value(Primary::Bool(true), keyword("true")),
+        value(Primary::Bool(false), keyword("false")),
+    ))(input)
+}
+
+fn null(input: &str) -> IResult<'_, Primary> {
+    map(keyword("null"), |_| Primary::Null)(input)
+}
+
+fn primary(input: &str) -> IResult<'_, Primary> {
     alt((
         number,
         string_literal,
+        block,
         immediate_block,
         list,
         function,
-        block,
+        group,
+        boolean,
         null,
+        import,
         variable,
     ))(input)
 }
 
-fn access(input: &str) -> IResult<&str, OperationRight> {
+fn access(input: &str) -> IResult<'_, OperationRight> {
     map(
         alt((
-            preceded(char('.'), identifier),
+            preceded(char('.'), cut(identifier)),
             delimited(char('['), string, char(']')),
         )),
         OperationRight::Access,
     )(input)
 }
 
-fn operation(input: &str) -> IResult<&str, Operation> {
-    let (input, left) = preceded(multispace0, primary)(input)?;
-    let (input, rights) = terminated(many0(alt((access, call, index))), multispace0)(input)?;
+fn operation(input: &str) -> IResult<'_, Operation> {
+    let (input, _) = space(input)?;
+    let start = input.len();
+    let (input, left) = context("expression", primary)(input)?;
+    let (input, rights) = terminated(
+        many0(map(spanned(alt((access, call, index))), |(right, span)| {
+            (right, Span::new(start, span.end))
+        })),
+        space,
+    )(input)?;
     Ok((input, Operation { left, rights }))
 }
 
-fn multitive(input: &str) -> IResult<&str, Multitive> {
-    let (input, left) = operation(input)?;
-    let (input, rights) = fold_many0(
-        pair(alt((char('*'), char('/'), char('%'))), operation),
-        Vec::new(),
-        |mut vec, (op, val)| {
-            match op {
-                '*' => vec.push(MultitiveRight::Mul(val)),
-                '/' => vec.push(MultitiveRight::Div(val)),
-                '%' => vec.push(MultitiveRight::Surplus(val)),
-                _ => unreachable!(),
-            };
-            vec
-        },
-    )(input)?;
-    Ok((input, Multitive { left, rights }))
-}
-
-fn additive(input: &str) -> IResult<&str, Additive> {
-    let (input, left) = multitive(input)?;
-    let (input, rights) = fold_many0(
-        pair(alt((char('+'), char('-'))), multitive),
-        Vec::new(),
-        |mut vec, (op, val)| {
-            match op {
-                '+' => vec.push(AdditiveRight::Add(val)),
-                '-' => vec.push(AdditiveRight::Sub(val)),
-                _ => unreachable!(),
-            };
-            vec
-        },
-    )(input)?;
-    Ok((input, Additive { left, rights }))
+fn unary_operator(input: &str) -> IResult<'_, UnaryOp> {
+    alt((
+        value(UnaryOp::Not, char('!')),
+        // A minus sign directly before digits belongs to the number literal.
+        value(UnaryOp::Neg, terminated(char('-'), not(peek(digit1)))),
+    ))(input)
 }
 
-fn comparison(input: &str) -> IResult<&str, Comparison> {
-    let (input, left) = additive(input)?;
-    let (input, rights) = fold_many0(
-        pair(
-            alt((
-                tag("="),
-                tag("!="),
-                tag(">"),
-                tag("<"),
-                tag(">="),
-                tag("<="),
-            )),
-            additive,
-        ),
-        Vec::new(),
-        |mut vec, (op, val)| {
-            match op {
-                "=" => vec.push(ComparisonRight::Equal(val)),
-                "!=" => vec.push(ComparisonRight::NotEqual(val)),
-                ">" => vec.push(ComparisonRight::GreaterThan(val)),
-                "<" => vec.push(ComparisonRight::LessThan(val)),
-                ">=" => vec.push(ComparisonRight::NotLessThan(val)),
-                "<=" => vec.push(ComparisonRight::NotGreaterThan(val)),
-                _ => unreachable!(),
-            };
-            vec
-        },
-    )(input)?;
-    Ok((input, Comparison { left, rights }))
+fn unary(input: &str) -> IResult<'_, Expression> {
+    let (rest, operator) = opt(spanned(preceded(space, unary_operator)))(input)?;
+    match operator {
+        Some((op, span)) => map(cut(unary), |operand| Expression::Unary {
+            op,
+            operand: Box::new(operand),
+            span,
+        })(rest),
+        None => map(operation, Expression::Operation)(input),
+    }
 }
 
-fn bind(input: &str) -> IResult<&str, (String, Expression)> {
-    let (input, (label, v)) = separated_pair(identifier, char(':'), expression)(input)?;
+fn binary_operator(input: &str) -> IResult<'_, BinaryOp> {
+    // Operators sharing a prefix are listed longest first.
+    alt((
+        value(BinaryOp::Or, tag("||")),
+        value(BinaryOp::And, tag("&&")),
+        value(BinaryOp::NotEqual, tag("!=")),
+        value(BinaryOp::NotLessThan, tag(">=")),
+        value(BinaryOp::NotGreaterThan, tag("<=")),
+        value(BinaryOp::Equal, tag("=")),
+        value(BinaryOp::GreaterThan, tag(">")),
+        value(BinaryOp::LessThan, tag("<")),
+        value(BinaryOp::Add, char('+')),
+        value(BinaryOp::Sub, char('-')),
+        value(BinaryOp::Mul, char('*')),
+        value(BinaryOp::Div, char('/')),
+        value(BinaryOp::Surplus, char('%')),
+    ))(input)
+}
+
+/// Binding power of binary operators; all of them associate to the left,
+/// though the translator rejects chained comparisons.
+fn precedence(op: BinaryOp) -> u8 {
+    use BinaryOp::*;
+    match op {
+        Or => 1,
+        And => 2,
+        Equal | NotEqual | GreaterThan | LessThan | NotGreaterThan | NotLessThan => 3,
+        Add | Sub => 4,
+        Mul | Div | Surplus => 5,
+    }
+}
+
+/// Parses operators binding at least as tightly as `min_precedence` by precedence climbing.
+fn binary(input: &str, min_precedence: u8) -> IResult<'_, Expression> {
+    let (mut input, mut left) = unary(input)?;
+    loop {
+        let (rest, (op, span)) = match spanned(preceded(space, binary_operator))(input) {
+            Ok(result) => result,
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+        if precedence(op) < min_precedence {
+            break;
+        }
+        let (rest, right) = cut(|input| binary(input, precedence(op) + 1))(rest)?;
+        left = Expression::Binary {
+            op,
+            left: Box::new(left),
+            right: Box::new(right),
+            span,
+        };
+        input = rest;
+    }
+    Ok((input, left))
+}
+
+fn bind(input: &str) -> IResult<'_, (String, Expression)> {
+    let (input, (label, v)) = pair(identifier, preceded(char(':'), cut(expression)))(input)?;
     Ok((input, (label, v)))
 }
 
-fn definitions(input: &str) -> IResult<&str, Vec<(String, Expression)>> {
-    separated_list(char(','), delimited(multispace0, bind, multispace0))(input)
+fn definitions(input: &str) -> IResult<'_, Vec<(String, Expression)>> {
+    separated_list(char(','), delimited(space, bind, space))(input)
 }
 
-fn statement(input: &str) -> IResult<&str, Statement> {
-    alt((
-        map(
-            separated_pair(definitions, char(','), expression),
-            |(definitions, body)| Statement { definitions, body },
-        ),
-        map(expression, |body| Statement {
+fn statement(input: &str) -> IResult<'_, Statement> {
+    let (input, definitions) = definitions(input)?;
+    if definitions.is_empty() {
+        return map(expression, |body| Statement {
             definitions: Vec::new(),
             body,
-        }),
-    ))(input)
+        })(input);
+    }
+    let (input, body) = preceded(char(','), cut(expression))(input)?;
+    Ok((input, Statement { definitions, body }))
 }
 
-fn if_(input: &str) -> IResult<&str, Expression> {
-    let (input, (cond, cons, alt)) = delimited(
-        multispace0,
-        preceded(tag("if"), tuple((expression, expression, expression))),
-        multispace0,
+fn entry(input: &str) -> IResult<'_, Entry> {
+    let (input, definitions) = definitions(input)?;
+    if definitions.is_empty() {
+        return map(expression, |body| Entry {
+            definitions: Vec::new(),
+            body: Some(body),
+        })(input);
+    }
+    let (input, body) = opt(preceded(char(','), cut(expression)))(input)?;
+    Ok((input, Entry { definitions, body }))
+}
+
+fn if_(input: &str) -> IResult<'_, Expression> {
+    let (input, ((cond, cond_span), cons, alt)) = delimited(
+        space,
+        preceded(
+            keyword("if"),
+            cut(tuple((spanned(expression), expression, expression))),
+        ),
+        space,
     )(input)?;
     Ok((
         input,
@@ -224,19 +648,192 @@ fn if_(input: &str) -> IResult<&str, Expression> {
             cond: Box::new(cond),
             cons: Box::new(cons),
             alt: Box::new(alt),
+            cond_span,
         },
     ))
 }
 
-fn expression(input: &str) -> IResult<&str, Expression> {
-    alt((if_, map(comparison, Expression::Comparison)))(input)
+fn expression(input: &str) -> IResult<'_, Expression> {
+    context("expression", alt((if_, |input| binary(input, 0))))(input)
 }
 
-pub fn parse(input: &str) -> IResult<&str, AST> {
-    all_consuming(statement)(input)
+pub fn parse(source: &Arc<Source>) -> Result<Ast, Diagnostic> {
+    finish(source, all_consuming(statement)(&source.text))
+}
+
+pub fn parse_entry(source: &Arc<Source>) -> Result<Entry, Diagnostic> {
+    finish(source, all_consuming(entry)(&source.text))
 }
 
 #[test]
 fn test_definitions() {
     dbg!(definitions("hoge: 1, fuga:2").unwrap());
 }
+
+#[test]
+fn test_entry() {
+    let entry = parse_entry(&Arc::new(Source::new("test", "hoge: 1, fuga: 2\n"))).unwrap();
+    assert_eq!(entry.definitions.len(), 2);
+    assert!(entry.body.is_none());
+
+    let entry = parse_entry(&Arc::new(Source::new("test", "hoge: 1, hoge + 1\n"))).unwrap();
+    assert_eq!(entry.definitions.len(), 1);
+    assert!(entry.body.is_some());
+}
+
+#[test]
+fn test_error() {
+    let parse_error = |text: &str| {
+        let e = parse(&Arc::new(Source::new("test", text))).unwrap_err();
+        (e.message, e.location.start)
+    };
+
+    assert_eq!(
+        parse_error("a: 1,\na + )"),
+        ("expected expression, found `)`".to_string(), 10)
+    );
+    assert_eq!(
+        parse_error("a: 1 +\n"),
+        ("expected expression, found end of input".to_string(), 6)
+    );
+    assert_eq!(
+        parse_error("a: 1, b: 2"),
+        ("expected `,`, found end of input".to_string(), 10)
+    );
+    assert_eq!(
+        parse_error("f(1, 2"),
+        ("expected `)`, found end of input".to_string(), 6)
+    );
+    assert_eq!(parse_error("[1, 2] 3"), ("unexpected `3`".to_string(), 7));
+}
+
+#[test]
+fn test_number() {
+    let number = |text| match number(text) {
+        Ok(("", Primary::Number(n))) => n,
+        result => panic!("{:?}", result),
+    };
+    assert_eq!(number("42"), 42.0);
+    assert_eq!(number("-3"), -3.0);
+    assert_eq!(number("1.5"), 1.5);
+    assert_eq!(number("-0.25"), -0.25);
+    assert_eq!(number("1e9"), 1e9);
+    assert_eq!(number("2.5E-3"), 2.5e-3);
+    assert_eq!(number("1e+2"), 100.0);
+    assert_eq!(number("0xff"), 255.0);
+    assert_eq!(number("-0XDead_Beef"), -3735928559.0);
+    assert_eq!(number("1_000_000"), 1e6);
+    assert_eq!(number("1.234_5"), 1.2345);
+
+    let parse_error = |text: &str| {
+        let e = parse(&Arc::new(Source::new("test", text))).unwrap_err();
+        e.to_string().lines().next().unwrap().to_string()
+    };
+    assert_eq!(parse_error("1__0"), "expected digit, found `_`");
+    assert_eq!(parse_error("1_"), "expected digit, found end of input");
+    assert_eq!(parse_error("1e"), "expected digit, found end of input");
+    assert_eq!(parse_error("0x"), "expected digit, found end of input");
+}
+
+#[test]
+fn test_string() {
+    let string = |text| match string(text) {
+        Ok(("", s)) => s,
+        result => panic!("{:?}", result),
+    };
+    assert_eq!(string(r#""a\"b\\c""#), "a\"b\\c");
+    assert_eq!(string(r#""\n\t\r\0""#), "\n\t\r\0");
+    assert_eq!(string(r#""\u{1F600}\u{e9}""#), "\u{1F600}\u{e9}");
+    assert_eq!(string(r#"r"C:\path\n""#), r"C:\path\n");
+    assert_eq!(string(r###"r#"say "hi""#"###), r#"say "hi""#);
+    assert_eq!(
+        string("\"\"\"\n    a:\n      b\n\n    c\\t\"\n    \"\"\""),
+        "a:\n  b\n\nc\t\"\n"
+    );
+    assert_eq!(string("\"\"\"\n  a\n    b\"\"\""), "a\n  b");
+    assert_eq!(string("\"\"\"\n    a\n  \"\"\""), "  a\n");
+
+    let parse_error = |text: &str| {
+        let e = parse(&Arc::new(Source::new("test", text))).unwrap_err();
+        e.to_string().lines().next().unwrap().to_string()
+    };
+    assert_eq!(
+        parse_error(r#""\q""#),
+        "expected escape sequence, found `q`"
+    );
+    assert_eq!(parse_error(r#""abc"#), "expected `\"`, found end of input");
+    assert_eq!(
+        parse_error(r#"r"abc"#),
+        "expected end of raw string, found end of input"
+    );
+    assert_eq!(
+        parse_error("\"\"\"abc\"\"\""),
+        "expected newline, found `a`"
+    );
+    assert_eq!(
+        parse_error("\"\"\"\n  a \\x\n\"\"\""),
+        "expected escape sequence, found `x`"
+    );
+}
+
+#[test]
+fn test_comment() {
+    let source = Arc::new(Source::new(
+        "test",
+        "// leading\na: 1, /* a /* nested */ comment */\nb: [a, // trailing\n  2] // end\n,\nb",
+    ));
+    let ast = parse(&source).unwrap();
+    assert_eq!(ast.definitions.len(), 2);
+
+    let e = parse(&Arc::new(Source::new("test", "a: 1 /* /* */, a"))).unwrap_err();
+    assert_eq!(
+        e.to_string().lines().next().unwrap(),
+        "expected `*/`, found end of input"
+    );
+}
+
+/// Renders the operator structure of `expression` with explicit parentheses.
+#[cfg(test)]
+fn grouping(expression: &Expression) -> String {
+    match expression {
+        Expression::Binary {
+            op, left, right, ..
+        } => format!("({} {:?} {})", grouping(left), op, grouping(right)),
+        Expression::Unary { op, operand, .. } => format!("({:?} {})", op, grouping(operand)),
+        Expression::Operation(Operation {
+            left: Primary::Variable(name, _),
+            rights,
+        }) if rights.is_empty() => name.clone(),
+        Expression::Operation(Operation {
+            left: Primary::Group(expression),
+            rights,
+        }) if rights.is_empty() => grouping(expression),
+        _ => "_".to_string(),
+    }
+}
+
+#[test]
+fn test_precedence() {
+    let grouping = |text| match expression(text) {
+        Ok(("", expression)) => grouping(&expression),
+        result => panic!("{:?}", result),
+    };
+    assert_eq!(
+        grouping("a && !b || c != d && e"),
+        "((a And (Not b)) Or ((c NotEqual d) And e))"
+    );
+    assert_eq!(grouping("a - b - c"), "((a Sub b) Sub c)");
+    assert_eq!(grouping("a + b * c"), "(a Add (b Mul c))");
+    assert_eq!(grouping("(a + b) * c"), "((a Add b) Mul c)");
+    assert_eq!(grouping("-a * b"), "((Neg a) Mul b)");
+    assert_eq!(grouping("-(a * b)"), "(Neg (a Mul b))");
+    assert_eq!(grouping("a < b + c"), "(a LessThan (b Add c))");
+    assert_eq!(grouping("a >= b"), "(a NotLessThan b)");
+    assert_eq!(grouping("a <= -b"), "(a NotGreaterThan (Neg b))");
+    assert_eq!(grouping("a != b"), "(a NotEqual b)");
+    assert_eq!(grouping("( ( a ) )"), "a");
+
+    let eval = |text| crate::translator::eval(text).unwrap().to_string();
+    assert_eq!(eval("a: 1, b: 2, -(a + b) * 2"), "-6");
+    assert_eq!(eval("a: {x: 2}, (a).x * (1 + 2)"), "6");
+}
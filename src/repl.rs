@@ -0,0 +1,152 @@
+use anyhow::Result;
+use spctr::{Engine, Session};
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+pub fn start(engine: &Engine) -> Result<()> {
+    let mut session = engine.session()?;
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut input = String::new();
+    loop {
+        prompt(if input.is_empty() { "> " } else { ". " })?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        input.push_str(&line);
+        input.push('\n');
+        if is_incomplete(&input) {
+            continue;
+        }
+
+        let entry = mem::take(&mut input);
+        if entry.trim().is_empty() {
+            continue;
+        }
+        if let Err(e) = eval(&mut session, &entry) {
+            eprintln!("error: {}", e);
+        }
+    }
+
+    if !input.trim().is_empty() {
+        if let Err(e) = eval(&mut session, &input) {
+            eprintln!("error: {}", e);
+        }
+    }
+    println!();
+    Ok(())
+}
+
+fn prompt(s: &str) -> Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(s.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn eval(session: &mut Session, input: &str) -> Result<()> {
+    if let Some(value) = session.eval("<repl>", input)? {
+        println!("{}", value);
+    }
+    Ok(())
+}
+
+/// Whether `input` has unclosed strings, comments, braces, brackets or parentheses,
+/// meaning the entry continues on the next line.
+fn is_incomplete(input: &str) -> bool {
+    let chars: Vec<char> = input.chars().collect();
+    let mut depth = 0;
+    let mut in_string = false;
+    // Text blocks end only at an unescaped `"""`, so single quotes inside them don't toggle `in_string`.
+    let mut in_text_block = false;
+    // The number of `#`s closing the raw string being read, where backslashes are not escapes.
+    let mut raw_hashes: Option<usize> = None;
+    let mut in_line_comment = false;
+    let mut comment_depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        i += 1;
+        if in_line_comment {
+            in_line_comment = c != '\n';
+            continue;
+        }
+        if let Some(hashes) = raw_hashes {
+            if c == '"' && chars[i..].starts_with(&vec!['#'; hashes]) {
+                i += hashes;
+                raw_hashes = None;
+            }
+            continue;
+        }
+        if in_text_block {
+            if c == '\\' {
+                i += 1;
+            } else if c == '"' && chars[i..].starts_with(&['"', '"']) {
+                i += 2;
+                in_text_block = false;
+            }
+            continue;
+        }
+        match (c, next) {
+            ('/', Some('*')) if !in_string => {
+                i += 1;
+                comment_depth += 1;
+            }
+            ('*', Some('/')) if comment_depth > 0 => {
+                i += 1;
+                comment_depth -= 1;
+            }
+            _ if comment_depth > 0 => {}
+            ('/', Some('/')) if !in_string => in_line_comment = true,
+            ('r', _) if !in_string && starts_raw_string(&chars, i - 1) => {
+                let hashes = chars[i..].iter().take_while(|&&c| c == '#').count();
+                i += hashes + 1;
+                raw_hashes = Some(hashes);
+            }
+            ('"', Some('"')) if !in_string && chars.get(i + 1) == Some(&'"') => {
+                i += 2;
+                in_text_block = true;
+            }
+            ('"', _) => in_string = !in_string,
+            ('\\', _) if in_string => i += 1,
+            ('{', _) | ('[', _) | ('(', _) if !in_string => depth += 1,
+            ('}', _) | (']', _) | (')', _) if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    in_string || in_text_block || raw_hashes.is_some() || comment_depth > 0 || depth > 0
+}
+
+/// Whether the `r` at `i` opens a raw string, `r"` or `r#"`, rather than ending an identifier.
+fn starts_raw_string(chars: &[char], i: usize) -> bool {
+    let is_identifier = |c: &char| c.is_alphanumeric() || *c == '_';
+    if i > 0 && is_identifier(&chars[i - 1]) {
+        return false;
+    }
+    chars[i + 1..].iter().find(|&&c| c != '#') == Some(&'"')
+}
+
+#[test]
+fn test_is_incomplete() {
+    assert!(!is_incomplete("a: 1\n"));
+    assert!(is_incomplete("a: {\n"));
+    assert!(is_incomplete("f: (x) => [\n  x,\n"));
+    assert!(!is_incomplete("f: (x) => [\n  x\n]\n"));
+    assert!(!is_incomplete("s: \"{\"\n"));
+    assert!(!is_incomplete("s: \"\\\"{\"\n"));
+    assert!(is_incomplete("s: \"\"\"\n  a\n"));
+    assert!(is_incomplete("s: \"\"\"\n  say \"hi\n"));
+    assert!(!is_incomplete("s: \"\"\"\n  say \"hi\n  \"\"\", s\n"));
+    assert!(!is_incomplete("s: \"\"\"\n  \\\"\"\"\n  \"\"\"\n"));
+    assert!(!is_incomplete("s: \"\", t: 1\n"));
+    assert!(!is_incomplete("a: 1 // {\n"));
+    assert!(is_incomplete("a: 1 /* /* */\n"));
+    assert!(!is_incomplete("a: [1 /* ] */]\n"));
+    assert!(!is_incomplete("p: r\"C:\\\"\n"));
+    assert!(!is_incomplete("p: r#\"a \"{\" b\"#\n"));
+    assert!(is_incomplete("p: r#\"a\"\n"));
+    assert!(!is_incomplete("bar: \"{\"\n"));
+}
@@ -0,0 +1,761 @@
+use crate::vm::Value;
+use ::serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use ::serde::ser::{self, Serialize};
+use ::serde::{forward_to_deserialize_any, Deserializer};
+use std::fmt;
+use std::rc::Rc;
+use std::vec;
+
+/// An error converting between values and Rust data through serde.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+impl Value {
+    /// Converts an evaluated value into `T`, with blocks as structs or maps, lists as sequences
+    /// and `null` as `None` or `()`.
+    pub fn deserialize<T: DeserializeOwned>(self) -> Result<T, Error> {
+        T::deserialize(self)
+    }
+}
+
+/// Converts `data` into a value, as for binding host data with `Engine::bind`.
+/// Structs and maps with string keys become blocks, and sequences become lists.
+pub fn to_value<T: Serialize + ?Sized>(data: &T) -> Result<Value, Error> {
+    data.serialize(Serializer)
+}
+
+impl Value {
+    fn fields(self) -> Result<Vec<(String, Value)>, Error> {
+        self.into_fields().map_err(|e| Error(e.to_string()))
+    }
+
+    fn unexpected(&self) -> de::Unexpected<'_> {
+        match self {
+            Value::Number(n) => match integer(*n) {
+                Some(n) => de::Unexpected::Signed(n),
+                None => de::Unexpected::Float(*n),
+            },
+            Value::Bool(b) => de::Unexpected::Bool(*b),
+            Value::String(s) => de::Unexpected::Str(s),
+            Value::Function(_) => de::Unexpected::Other("function"),
+            Value::List(_) => de::Unexpected::Seq,
+            Value::Null => de::Unexpected::Unit,
+            Value::Block(_) => de::Unexpected::Map,
+        }
+    }
+
+    /// The number as an integer, if it is one within `min..end`.
+    fn integer(&self, min: f64, end: f64, exp: &dyn de::Expected) -> Result<f64, Error> {
+        match *self {
+            Value::Number(n) if n.fract() == 0.0 && n >= min && n < end => Ok(n),
+            Value::Number(_) => Err(de::Error::invalid_value(self.unexpected(), exp)),
+            _ => Err(de::Error::invalid_type(self.unexpected(), exp)),
+        }
+    }
+}
+
+/// Whole numbers are given to visitors as integers, so that untyped data keeps them apart from floats.
+fn integer(n: f64) -> Option<i64> {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
+macro_rules! deserialize_integer {
+    ($($method:ident => $visit:ident($ty:ty),)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                // `MAX + 1` is a power of two, so it is exact even where `MAX` rounds up to it.
+                let end = <$ty>::MAX as f64 + 1.0;
+                let n = self.integer(<$ty>::MIN as f64, end, &visitor)?;
+                visitor.$visit(n as $ty)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Number(n) => match integer(n) {
+                Some(n) => visitor.visit_i64(n),
+                None => visitor.visit_f64(n),
+            },
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::String(s) => visitor.visit_string((*s).clone()),
+            Value::Function(_) => Err(de::Error::custom("cannot deserialize a function")),
+            Value::List(values) => {
+                let values = Rc::try_unwrap(values).unwrap_or_else(|values| (*values).clone());
+                let mut seq = Seq(values.into_iter());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Null => visitor.visit_unit(),
+            Value::Block(_) => visitor.visit_map(Map {
+                fields: self.fields()?.into_iter(),
+                value: None,
+            }),
+        }
+    }
+
+    deserialize_integer! {
+        deserialize_i8 => visit_i8(i8),
+        deserialize_i16 => visit_i16(i16),
+        deserialize_i32 => visit_i32(i32),
+        deserialize_i64 => visit_i64(i64),
+        deserialize_u8 => visit_u8(u8),
+        deserialize_u16 => visit_u16(u16),
+        deserialize_u32 => visit_u32(u32),
+        deserialize_u64 => visit_u64(u64),
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are written as strings, and the others as blocks of a single field named after the variant.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Value::String(s) => visitor.visit_enum((*s).clone().into_deserializer()),
+            Value::Block(_) => {
+                let mut fields = self.fields()?;
+                if fields.len() != 1 {
+                    return Err(de::Error::custom(format!(
+                        "expected a block with a single field for an enum, found {} fields",
+                        fields.len()
+                    )));
+                }
+                let (variant, value) = fields.remove(0);
+                visitor.visit_enum(Enum { variant, value })
+            }
+            value => Err(de::Error::invalid_type(
+                value.unexpected(),
+                &"string or block",
+            )),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool f32 f64 char str string bytes byte_buf unit unit_struct seq tuple tuple_struct
+        map struct identifier ignored_any i128 u128
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+struct Seq(vec::IntoIter<Value>);
+
+impl Seq {
+    fn end(&self) -> Result<(), Error> {
+        match self.0.len() {
+            0 => Ok(()),
+            rest => Err(de::Error::custom(format!(
+                "{} more elements in list than expected",
+                rest
+            ))),
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for Seq {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.0
+            .next()
+            .map(|value| seed.deserialize(value))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct Map {
+    fields: vec::IntoIter<(String, Value)>,
+    value: Option<(String, Value)>,
+}
+
+impl<'de> MapAccess<'de> for Map {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.fields.next() {
+            Some((name, value)) => {
+                let key = seed.deserialize(name.clone().into_deserializer())?;
+                self.value = Some((name, value));
+                Ok(Some(key))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Errors in the value of a field are prefixed with its name.
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let (name, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value is missing"))?;
+        seed.deserialize(value)
+            .map_err(|Error(e)| Error(format!("field `{}`: {}", name, e)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len())
+    }
+}
+
+struct Enum {
+    variant: String,
+    value: Value,
+}
+
+impl<'de> EnumAccess<'de> for Enum {
+    type Error = Error;
+    type Variant = Value;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Value), Error> {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Value {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+}
+
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = Error;
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeBlock;
+    type SerializeStruct = SerializeBlock;
+    type SerializeStructVariant = SerializeBlock;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Error> {
+        Ok(Value::bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, Error> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, Error> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, Error> {
+        Ok(Value::number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, Error> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, Error> {
+        Ok(Value::string(Rc::new(v.to_string())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Error> {
+        let bytes = v.iter().map(|&b| Value::number(b.into())).collect();
+        Ok(Value::list(Rc::new(bytes)))
+    }
+
+    fn serialize_none(self) -> Result<Value, Error> {
+        Ok(Value::null())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, Error> {
+        Ok(Value::null())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Error> {
+        Ok(Value::null())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, Error> {
+        Ok(Value::from_fields(vec![(
+            variant.to_string(),
+            value.serialize(self)?,
+        )]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            variant: None,
+            values: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        Ok(SerializeList {
+            variant: Some(variant),
+            values: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeBlock, Error> {
+        Ok(SerializeBlock {
+            variant: None,
+            fields: Vec::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeBlock, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeBlock, Error> {
+        Ok(SerializeBlock {
+            variant: Some(variant),
+            fields: Vec::new(),
+            key: None,
+        })
+    }
+}
+
+/// Wraps `value` in a block of a single field named `variant`, the way enum variants are written.
+fn with_variant(variant: Option<&'static str>, value: Value) -> Value {
+    match variant {
+        Some(variant) => Value::from_fields(vec![(variant.to_string(), value)]),
+        None => value,
+    }
+}
+
+struct SerializeList {
+    variant: Option<&'static str>,
+    values: Vec<Value>,
+}
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.values.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        Ok(with_variant(
+            self.variant,
+            Value::list(Rc::new(self.values)),
+        ))
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        SerializeList::end(self)
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        SerializeList::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        SerializeList::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        SerializeList::end(self)
+    }
+}
+
+struct SerializeBlock {
+    variant: Option<&'static str>,
+    fields: Vec<(String, Value)>,
+    key: Option<String>,
+}
+
+impl SerializeBlock {
+    fn end(self) -> Result<Value, Error> {
+        Ok(with_variant(self.variant, Value::from_fields(self.fields)))
+    }
+}
+
+impl ser::SerializeMap for SerializeBlock {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        match key.serialize(Serializer)? {
+            Value::String(s) => {
+                self.key = Some((*s).clone());
+                Ok(())
+            }
+            key => Err(ser::Error::custom(format!(
+                "map keys must be strings, found {}",
+                key.type_name()
+            ))),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ser::Error::custom("key is missing"))?;
+        self.fields.push((key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        SerializeBlock::end(self)
+    }
+}
+
+impl ser::SerializeStruct for SerializeBlock {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.fields
+            .push((key.to_string(), value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        SerializeBlock::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeBlock {
+    type Ok = Value;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<Value, Error> {
+        SerializeBlock::end(self)
+    }
+}
+
+#[test]
+fn test_deserialize() {
+    use crate::Engine;
+    use ::serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Mode {
+        Fast,
+        Limited { rate: f64 },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Server {
+        host: String,
+        port: u16,
+        tls: Option<bool>,
+        mode: Mode,
+        limit: Mode,
+        tags: Vec<String>,
+        env: HashMap<String, String>,
+    }
+
+    let value = Engine::new()
+        .eval_str(
+            r#"{
+                host: "example.com",
+                port: 8000 + 80,
+                tls: null,
+                mode: "Fast",
+                limit: { Limited: { rate: 0.5 } },
+                tags: ["a", "b"],
+                env: { HOME: "/root" }
+            }"#,
+        )
+        .unwrap();
+    let server: Server = value.deserialize().unwrap();
+    assert_eq!(
+        server,
+        Server {
+            host: "example.com".to_string(),
+            port: 8080,
+            tls: None,
+            mode: Mode::Fast,
+            limit: Mode::Limited { rate: 0.5 },
+            tags: vec!["a".to_string(), "b".to_string()],
+            env: vec![("HOME".to_string(), "/root".to_string())]
+                .into_iter()
+                .collect(),
+        }
+    );
+
+    let error = |text: &str| {
+        Engine::new()
+            .eval_str(text)
+            .unwrap()
+            .deserialize::<HashMap<String, u8>>()
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(
+        error("{a: 256}"),
+        "field `a`: invalid value: integer `256`, expected u8"
+    );
+    assert_eq!(
+        error("{a: \"1\"}"),
+        "field `a`: invalid type: string \"1\", expected u8"
+    );
+
+    let two_to = |bits| Value::number(2f64.powi(bits));
+    assert_eq!(two_to(63).deserialize::<u64>().unwrap(), 1 << 63);
+    assert!(two_to(64).deserialize::<u64>().is_err());
+    assert!(two_to(63).deserialize::<i64>().is_err());
+    assert_eq!(
+        Value::number(-2f64.powi(63)).deserialize::<i64>().unwrap(),
+        i64::MIN
+    );
+}
+
+#[test]
+fn test_to_value() {
+    use crate::Engine;
+    use ::serde::Serialize;
+
+    #[derive(Serialize)]
+    enum Level {
+        Debug,
+        Custom(u8),
+    }
+
+    #[derive(Serialize)]
+    struct Host {
+        name: &'static str,
+        ports: Vec<u16>,
+        level: Level,
+        fallback: Option<Level>,
+        owner: Option<()>,
+    }
+
+    let value = to_value(&Host {
+        name: "web",
+        ports: vec![80, 443],
+        level: Level::Debug,
+        fallback: Some(Level::Custom(3)),
+        owner: None,
+    })
+    .unwrap();
+    assert_eq!(
+        value.to_string(),
+        r#"{name: "web", ports: [80, 443], level: "Debug", fallback: {Custom: 3}, owner: null}"#
+    );
+
+    let mut engine = Engine::new();
+    engine.bind("host", value);
+    let value = engine
+        .eval_str("host.ports[1] + host.fallback.Custom")
+        .unwrap();
+    assert_eq!(value.as_number(), Some(446.0));
+
+    let map: std::collections::HashMap<u8, u8> = vec![(1, 2)].into_iter().collect();
+    assert_eq!(
+        to_value(&map).unwrap_err().to_string(),
+        "map keys must be strings, found number"
+    );
+}
@@ -0,0 +1,44 @@
+use crate::host::{Caller, HostModule};
+use crate::vm::Value;
+use anyhow::{anyhow, Result};
+use std::rc::Rc;
+
+pub fn module() -> HostModule {
+    HostModule::new()
+        .function("concat", concat)
+        .function("map", map)
+        .function("filter", filter)
+}
+
+fn concat(a: Rc<Vec<Value>>, b: Rc<Vec<Value>>) -> Result<Value> {
+    let mut list = (*a).clone();
+    list.extend(b.iter().cloned());
+    Ok(Value::list(Rc::new(list)))
+}
+
+fn map(caller: &mut Caller<'_>, list: Rc<Vec<Value>>, f: Value) -> Result<Value> {
+    let list = list
+        .iter()
+        .map(|el| caller.call(&f, vec![el.clone()]))
+        .collect::<Result<_>>()?;
+    Ok(Value::list(Rc::new(list)))
+}
+
+fn filter(caller: &mut Caller<'_>, list: Rc<Vec<Value>>, f: Value) -> Result<Value> {
+    let mut filtered = Vec::new();
+    for el in list.iter() {
+        let keep = match caller.call(&f, vec![el.clone()])? {
+            Value::Bool(b) => b,
+            v => {
+                return Err(anyhow!(
+                    "filter predicate must return a bool, found {}",
+                    v.type_name()
+                ))
+            }
+        };
+        if keep {
+            filtered.push(el.clone());
+        }
+    }
+    Ok(Value::list(Rc::new(filtered)))
+}
@@ -0,0 +1,12 @@
+use crate::host::HostModule;
+use crate::vm::Value;
+use anyhow::Result;
+use std::rc::Rc;
+
+pub fn module() -> HostModule {
+    HostModule::new().function("concat", concat)
+}
+
+fn concat(a: Rc<String>, b: Rc<String>) -> Result<Value> {
+    Ok(Value::string(Rc::new(format!("{}{}", a, b))))
+}
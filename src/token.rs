@@ -1,68 +1,121 @@
-pub type AST = Statement;
+pub type Ast = Statement;
 
 pub type Bind = (String, Expression);
 
+/// Position of a node in its source.
+/// Parsers only see the input left to parse, so the bounds are kept as the lengths of the rest of the input;
+/// `Span::range` converts them into byte offsets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(rest_at_start: usize, rest_at_end: usize) -> Span {
+        Span {
+            start: rest_at_start,
+            end: rest_at_end,
+        }
+    }
+
+    pub fn range(&self, source_len: usize) -> (usize, usize) {
+        (source_len - self.start, source_len - self.end)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Statement {
     pub definitions: Vec<Bind>,
     pub body: Expression,
 }
 
+/// A single REPL input: binds which stay defined for later entries, optionally followed by a body to evaluate.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub definitions: Vec<Bind>,
+    pub body: Option<Expression>,
+}
+
 #[derive(Clone, Debug)]
 pub enum Expression {
-    Comparison(Comparison),
+    /// `span` points at the operator.
+    Binary {
+        op: BinaryOp,
+        left: Box<Expression>,
+        right: Box<Expression>,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expression>,
+        span: Span,
+    },
+    Operation(Operation),
     If {
         cond: Box<Expression>,
         cons: Box<Expression>,
         alt: Box<Expression>,
+        cond_span: Span,
     },
 }
 
-#[derive(Clone, Debug)]
-pub struct Comparison {
-    pub left: Additive,
-    pub rights: Vec<ComparisonRight>,
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    GreaterThan,
+    LessThan,
+    NotGreaterThan,
+    NotLessThan,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Surplus,
 }
 
-#[derive(Clone, Debug)]
-pub enum ComparisonRight {
-    Equal(Additive),
-    NotEqual(Additive),
-    GreaterThan(Additive),
-    LessThan(Additive),
-    NotGreaterThan(Additive),
-    NotLessThan(Additive),
-}
+impl BinaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Or => "||",
+            BinaryOp::And => "&&",
+            BinaryOp::Equal => "=",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::GreaterThan => ">",
+            BinaryOp::LessThan => "<",
+            BinaryOp::NotGreaterThan => "<=",
+            BinaryOp::NotLessThan => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Surplus => "%",
+        }
+    }
 
-#[derive(Clone, Debug)]
-pub struct Additive {
-    pub left: Multitive,
-    pub rights: Vec<AdditiveRight>,
+    pub fn is_comparison(self) -> bool {
+        use BinaryOp::*;
+        matches!(
+            self,
+            Equal | NotEqual | GreaterThan | LessThan | NotGreaterThan | NotLessThan
+        )
+    }
 }
 
-#[derive(Clone, Debug)]
-pub enum AdditiveRight {
-    Add(Multitive),
-    Sub(Multitive),
-}
-
-#[derive(Clone, Debug)]
-pub struct Multitive {
-    pub left: Operation,
-    pub rights: Vec<MultitiveRight>,
-}
-
-#[derive(Clone, Debug)]
-pub enum MultitiveRight {
-    Mul(Operation),
-    Div(Operation),
-    Surplus(Operation),
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
 }
 
+/// The spans of the rights of an `Operation` cover the whole operation up to the right.
 #[derive(Clone, Debug)]
 pub struct Operation {
     pub left: Primary,
-    pub rights: Vec<OperationRight>,
+    pub rights: Vec<(OperationRight, Span)>,
 }
 
 #[derive(Clone, Debug)]
@@ -75,11 +128,15 @@ pub enum OperationRight {
 #[derive(Clone, Debug)]
 pub enum Primary {
     Number(f64),
+    Bool(bool),
     String(String),
-    Variable(String),
+    Variable(String, Span),
     ImmediateBlock(Box<Statement>),
     Block(Vec<Bind>),
     List(Vec<Expression>),
     Function(Vec<String>, Box<Expression>),
+    Group(Box<Expression>),
     Null,
+    /// `import "path"`, with the path as written.
+    Import(String, Span),
 }
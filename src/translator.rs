@@ -1,9 +1,81 @@
-use crate::lib;
-use crate::parser;
+use crate::diagnostic::{self, Diagnostic, Location, Source};
+use crate::module::{Content, Library, Loader, Module};
+use crate::stdlib;
 use crate::token::*;
-use crate::vm::{Cmd, ForeignFunction, Value};
+use crate::vm::{Cmd, Code, Value};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::mem;
 use std::rc::Rc;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownIdentifier {
+        name: String,
+        suggestion: Option<String>,
+        location: Location,
+    },
+    /// `a < b < c`, which would compare the bool `a < b` with `c`.
+    ChainedComparison {
+        first: BinaryOp,
+        second: BinaryOp,
+        location: Location,
+    },
+    /// An import which cannot be loaded, found before translating.
+    Import(Diagnostic),
+}
+
+impl Error {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            Error::UnknownIdentifier {
+                name,
+                suggestion,
+                location,
+            } => {
+                let diagnostic =
+                    Diagnostic::new(format!("unknown identifier `{}`", name), location.clone());
+                match suggestion {
+                    Some(s) => diagnostic.with_note(format!("help: did you mean `{}`?", s)),
+                    None => diagnostic,
+                }
+            }
+            Error::ChainedComparison {
+                first,
+                second,
+                location,
+            } => Diagnostic::new("comparison operators cannot be chained", location.clone())
+                .with_note(format!(
+                    "help: combine the comparisons with `&&`, as in `a {} b && b {} c`",
+                    first.symbol(),
+                    second.symbol()
+                )),
+            Error::Import(diagnostic) => diagnostic.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.diagnostic())
+    }
+}
+
+/// Every error found while translating a program.
+#[derive(Debug)]
+pub struct Errors(pub Vec<Error>);
+
+impl fmt::Display for Errors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<_> = self.0.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", errors.join("\n\n"))
+    }
+}
+
+impl error::Error for Errors {}
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Env(Option<Rc<(HashMap<String, usize>, Env)>>);
@@ -21,42 +93,161 @@ impl Env {
     }
 
     fn get_bind(&self, name: &str) -> Option<(usize, usize)> {
-        let rc = self.0.as_ref().unwrap();
+        let rc = self.0.as_ref()?;
         rc.0.get(name).map_or_else(
             || rc.1.get_bind(name).map(|(addr, depth)| (addr, depth + 1)),
             |addr| Some((*addr, 0)),
         )
     }
-}
 
-pub fn get_cmd(ast: &AST) -> Vec<Cmd> {
-    let mut translator = Translator::new();
-    let mut block = translator.block();
+    fn names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut env = self;
+        while let Some(rc) = env.0.as_ref() {
+            names.extend(rc.0.keys().map(String::as_str));
+            env = &rc.1;
+        }
+        names
+    }
+}
 
-    block.add_bind("Iterator", |translator| {
-        let token = parser::parse(include_str!("lib/iterator.spc")).unwrap().1;
-        translator.translate(&token)
+fn add_prelude(block: &mut BlockTranslator) {
+    block.add_bind("compare", |translator| {
+        translator.translate_primitive(2, Cmd::Compare)
+    });
+    block.add_bind("import_json", |translator| {
+        translator.translate_primitive(1, Cmd::ImportJson)
+    });
+    block.add_bind("List", |translator| {
+        translator.translate_value(stdlib::list::module().into_value())
+    });
+    block.add_bind("String", |translator| {
+        translator.translate_value(stdlib::string::module().into_value())
     });
+}
 
-    block.add_bind("List", lib::list::get_module);
-    block.add_bind("String", lib::string::get_module);
+/// Binds the modules so that they are evaluated once, when first imported.
+fn add_modules(block: &mut BlockTranslator, modules: Vec<Module>) {
+    for Module {
+        name,
+        source,
+        content,
+    } in modules
+    {
+        block.add_bind(name, move |translator| match content {
+            Content::Spctr(ast) => {
+                translator.with_source(source, |translator| translator.translate(&ast))
+            }
+            Content::Json(value) => translator.translate_value(value),
+            Content::Alias(name) => {
+                let (id, depth) = translator
+                    .get_bind(&name)
+                    .expect("aliased modules are bound in the same block");
+                Code::from(vec![Cmd::Load(id, depth)])
+            }
+        });
+    }
+}
+
+fn import_error(diagnostic: Diagnostic) -> Errors {
+    Errors(vec![Error::Import(diagnostic)])
+}
 
+/// Translates `ast` parsed from `source` into a program where `globals` are bound alongside the prelude,
+/// which includes the modules listed by the manifests of `library`.
+pub fn get_cmd(
+    source: Arc<Source>,
+    ast: &Ast,
+    globals: Vec<(String, Value)>,
+    library: &Library,
+) -> Result<Code, Errors> {
+    let mut loader = Loader::new(library.clone());
+    let prelude = loader.load_prelude().map_err(import_error)?;
+    let modules = loader
+        .load(&source, &ast.definitions, Some(&ast.body))
+        .map_err(import_error)?;
+    let mut translator = Translator::new(source);
+    translator.imports.borrow_mut().extend(prelude.imports);
+    translator.imports.borrow_mut().extend(modules.imports);
+    let mut block = translator.block();
+    add_prelude(&mut block);
+    add_modules(&mut block, prelude.modules);
+    for (name, value) in globals {
+        block.add_bind(name, move |translator| translator.translate_value(value));
+    }
+    add_modules(&mut block, modules.modules);
     block.set_body(|translator| translator.translate(ast));
-    block.finalize()
+    let cmd = block.finalize();
+    translator.finish(cmd)
+}
+
+/// Translator state shared by a sequence of entries, as in the REPL.
+/// Binds defined by an entry stay visible to every entry translated after it.
+#[derive(Clone)]
+pub struct Session {
+    translator: Translator,
+    loader: Loader,
 }
 
+impl Session {
+    /// Returns a new session along with the commands opening its prelude scope,
+    /// which have to be executed before any entry.
+    pub fn new(globals: Vec<(String, Value)>, library: Library) -> Result<(Session, Code), Errors> {
+        let mut loader = Loader::new(library);
+        let prelude = loader.load_prelude().map_err(import_error)?;
+        let mut translator = Translator::new(Arc::new(Source::new("<prelude>", "")));
+        translator.imports.borrow_mut().extend(prelude.imports);
+        let mut block = translator.block();
+        add_prelude(&mut block);
+        add_modules(&mut block, prelude.modules);
+        for (name, value) in globals {
+            block.add_bind(name, move |translator| translator.translate_value(value));
+        }
+        let (cmd, translator) = block.open();
+        Ok((Session { translator, loader }, cmd))
+    }
+
+    pub fn translate(&mut self, source: Arc<Source>, entry: &Entry) -> Result<Code, Errors> {
+        let modules = self
+            .loader
+            .load(&source, &entry.definitions, entry.body.as_ref())
+            .map_err(import_error)?;
+        self.translator.imports.borrow_mut().extend(modules.imports);
+        self.translator.source = source;
+        let mut cmd = Code::new();
+        if !entry.definitions.is_empty() || !modules.modules.is_empty() {
+            let mut block = self.translator.block();
+            for (name, body) in &entry.definitions {
+                block.add_bind(name, move |translator: &mut Translator| {
+                    translator.translate_expression(body)
+                });
+            }
+            add_modules(&mut block, modules.modules);
+            let (mut bind_cmd, translator) = block.open();
+            cmd.append(&mut bind_cmd);
+            self.translator = translator;
+        }
+        if let Some(body) = &entry.body {
+            cmd.append(&mut self.translator.translate_expression(body));
+        }
+        self.translator.finish(cmd)
+    }
+}
+
+type Emitter<'a> = Box<dyn FnOnce(&mut Translator) -> Code + 'a>;
+
 pub struct BlockTranslator<'a> {
     translator: &'a mut Translator,
     bind_names: Vec<String>,
-    bind_bodies: Vec<Box<dyn FnOnce(&mut Translator) -> Vec<Cmd> + 'a>>,
-    body: Option<Box<dyn FnOnce(&mut Translator) -> Vec<Cmd> + 'a>>,
+    bind_bodies: Vec<Emitter<'a>>,
+    body: Option<Emitter<'a>>,
 }
 
 impl<'a> BlockTranslator<'a> {
     pub fn add_bind<S, F>(&mut self, name: S, f: F)
     where
         S: ToString,
-        F: FnOnce(&mut Translator) -> Vec<Cmd> + 'a,
+        F: FnOnce(&mut Translator) -> Code + 'a,
     {
         self.bind_names.push(name.to_string());
         self.bind_bodies.push(Box::new(f));
@@ -64,48 +255,25 @@ impl<'a> BlockTranslator<'a> {
 
     fn set_body<F>(&mut self, f: F)
     where
-        F: FnOnce(&mut Translator) -> Vec<Cmd> + 'a,
+        F: FnOnce(&mut Translator) -> Code + 'a,
     {
         self.body = Some(Box::new(f));
     }
 
-    pub fn finalize(self) -> Vec<Cmd> {
-        let mut cmd = Vec::new();
-        let mut b = Vec::new();
-        let l = self.bind_names.len();
-        let mut map = HashMap::new();
-        for (id, name) in self.bind_names.into_iter().enumerate() {
-            map.insert(name, id);
-            b.push(id);
-        }
-
-        let mut translator = self.translator.fork(map);
-        let mut bind_cmds = Vec::new();
-        for (id, f) in b.into_iter().zip(self.bind_bodies) {
-            let mut body_cmd = f(&mut translator);
-            body_cmd.push(Cmd::Store(id));
-            body_cmd.push(Cmd::Return);
-            bind_cmds.push(body_cmd);
-        }
+    /// Emits the binds without a body and leaves their scope open.
+    /// Returns the commands along with a translator for the opened scope.
+    pub fn open(self) -> (Code, Translator) {
+        translate_binds(self.translator, self.bind_names, self.bind_bodies)
+    }
 
-        cmd.push(Cmd::Block(bind_cmds.iter().map(|cmd| cmd.len()).collect()));
-        cmd.append(&mut bind_cmds.into_iter().flatten().collect());
+    pub fn finalize(self) -> Code {
+        let (mut cmd, mut translator) =
+            translate_binds(self.translator, self.bind_names, self.bind_bodies);
 
         let mut body = if let Some(body_cmd) = self.body {
             (body_cmd)(&mut translator)
         } else {
-            let mut cmd = Vec::new();
-            let mut load_cmds = Vec::new();
-            for i in 0..l {
-                load_cmds.push(Cmd::Load(i, 0));
-                load_cmds.push(Cmd::Return);
-            }
-            cmd.push(Cmd::ConstructBlock(
-                load_cmds.len(),
-                Rc::new(translator.env.pop()),
-            ));
-            cmd.append(&mut load_cmds);
-            cmd
+            Code::from(vec![Cmd::ConstructBlock(Rc::new(translator.env.pop()))])
         };
 
         cmd.append(&mut body);
@@ -114,16 +282,80 @@ impl<'a> BlockTranslator<'a> {
     }
 }
 
+fn translate_binds(
+    translator: &Translator,
+    bind_names: Vec<String>,
+    bind_bodies: Vec<Emitter>,
+) -> (Code, Translator) {
+    let mut cmd = Code::new();
+    let mut b = Vec::new();
+    let mut map = HashMap::new();
+    for (id, name) in bind_names.into_iter().enumerate() {
+        map.insert(name, id);
+        b.push(id);
+    }
+
+    let mut translator = translator.fork(map);
+    let mut bind_cmds = Vec::new();
+    for (id, f) in b.into_iter().zip(bind_bodies) {
+        let mut body_cmd = f(&mut translator);
+        body_cmd.push(Cmd::Store(id));
+        body_cmd.push(Cmd::Return);
+        bind_cmds.push(body_cmd);
+    }
+
+    cmd.push(Cmd::Block(bind_cmds.iter().map(|cmd| cmd.len()).collect()));
+    for mut bind_cmd in bind_cmds {
+        cmd.append(&mut bind_cmd);
+    }
+    (cmd, translator)
+}
+
+#[derive(Clone)]
 pub struct Translator {
     env: Env,
+    source: Arc<Source>,
+    errors: Rc<RefCell<Vec<Error>>>,
+    /// The names of the modules bound for each import, as found by `module::load`.
+    imports: Rc<RefCell<HashMap<(String, String), String>>>,
 }
 
 impl Translator {
-    fn new() -> Translator {
-        Translator { env: Env(None) }
+    fn new(source: Arc<Source>) -> Translator {
+        Translator {
+            env: Env(None),
+            source,
+            errors: Rc::new(RefCell::new(Vec::new())),
+            imports: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Hands out `cmd` unless errors were found while translating it.
+    fn finish(&self, cmd: Code) -> Result<Code, Errors> {
+        let errors = mem::take(&mut *self.errors.borrow_mut());
+        if !errors.is_empty() {
+            return Err(Errors(errors));
+        }
+        Ok(cmd)
+    }
+
+    /// Translates with `f` while attributing locations to `source`.
+    pub fn with_source<F>(&mut self, source: Arc<Source>, f: F) -> Code
+    where
+        F: FnOnce(&mut Translator) -> Code,
+    {
+        let source = mem::replace(&mut self.source, source);
+        let cmd = f(self);
+        self.source = source;
+        cmd
+    }
+
+    fn location(&self, span: Span) -> Location {
+        let (start, end) = span.range(self.source.text.len());
+        Location::new(self.source.clone(), start, end)
     }
 
-    pub fn block(&mut self) -> BlockTranslator {
+    pub fn block(&mut self) -> BlockTranslator<'_> {
         BlockTranslator {
             translator: self,
             bind_bodies: Vec::new(),
@@ -135,28 +367,45 @@ impl Translator {
     pub fn fork(&self, map: HashMap<String, usize>) -> Translator {
         let mut forked_env = self.env.clone();
         forked_env.push(map);
-        Translator { env: forked_env }
+        Translator {
+            env: forked_env,
+            source: self.source.clone(),
+            errors: self.errors.clone(),
+            imports: self.imports.clone(),
+        }
     }
 
     fn get_bind(&self, name: &str) -> Option<(usize, usize)> {
         self.env.get_bind(name)
     }
 
-    fn translate(&mut self, v: &Statement) -> Vec<Cmd> {
+    fn translate(&mut self, v: &Statement) -> Code {
         let mut block = self.block();
         for (name, body) in &v.definitions {
             block.add_bind(name, move |translator: &mut Translator| {
-                translator.translate_expression(&body)
+                translator.translate_expression(body)
             });
         }
         block.set_body(move |translator| translator.translate_expression(&v.body));
         block.finalize()
     }
 
-    fn translate_expression(&mut self, v: &Expression) -> Vec<Cmd> {
+    fn translate_expression(&mut self, v: &Expression) -> Code {
         match v {
-            Expression::Comparison(a) => self.translate_comparison(a),
-            Expression::If { cond, cons, alt } => {
+            Expression::Binary {
+                op,
+                left,
+                right,
+                span,
+            } => self.translate_binary(*op, left, right, *span),
+            Expression::Unary { op, operand, span } => self.translate_unary(*op, operand, *span),
+            Expression::Operation(a) => self.translate_operation(a),
+            Expression::If {
+                cond,
+                cons,
+                alt,
+                cond_span,
+            } => {
                 let mut cond_cmd = self.translate_expression(cond);
 
                 let mut alt_cmd = self.translate_expression(alt);
@@ -164,10 +413,13 @@ impl Translator {
                 let mut cons_cmd = self.translate_expression(cons);
                 cons_cmd.push(Cmd::JumpRel(alt_cmd.len() + 1));
 
-                let mut cmd = Vec::new();
+                let mut cmd = Code::new();
 
                 cmd.append(&mut cond_cmd);
-                cmd.push(Cmd::JumpRelUnless(cons_cmd.len() + 1));
+                cmd.push_at(
+                    Cmd::JumpRelUnless(cons_cmd.len() + 1),
+                    self.location(*cond_span),
+                );
 
                 cmd.append(&mut cons_cmd);
                 cmd.append(&mut alt_cmd);
@@ -177,112 +429,117 @@ impl Translator {
         }
     }
 
-    fn translate_comparison(&mut self, v: &Comparison) -> Vec<Cmd> {
-        let mut cmd = self.translate_additive(&v.left);
-        for right in &v.rights {
-            match right {
-                ComparisonRight::Equal(r) => {
-                    cmd.append(&mut self.translate_additive(&r));
-                    cmd.push(Cmd::Equal);
-                }
-                ComparisonRight::NotEqual(r) => {
-                    cmd.append(&mut self.translate_additive(&r));
-                    cmd.push(Cmd::Equal);
-                    cmd.push(Cmd::Not);
-                }
-                ComparisonRight::GreaterThan(r) => {
-                    cmd.append(&mut self.translate_additive(&r));
-                    cmd.push(Cmd::GreaterThan);
-                }
-                ComparisonRight::LessThan(r) => {
-                    cmd.append(&mut self.translate_additive(&r));
-                    cmd.push(Cmd::LessThan);
-                }
-                ComparisonRight::NotGreaterThan(r) => {
-                    cmd.append(&mut self.translate_additive(&r));
-                    cmd.push(Cmd::GreaterThan);
-                    cmd.push(Cmd::Not);
-                }
-                ComparisonRight::NotLessThan(r) => {
-                    cmd.append(&mut self.translate_additive(&r));
-                    cmd.push(Cmd::LessThan);
-                    cmd.push(Cmd::Not);
-                }
+    fn translate_binary(
+        &mut self,
+        op: BinaryOp,
+        left: &Expression,
+        right: &Expression,
+        span: Span,
+    ) -> Code {
+        let location = self.location(span);
+        if let Expression::Binary { op: first, .. } = left {
+            if first.is_comparison() && op.is_comparison() {
+                self.errors.borrow_mut().push(Error::ChainedComparison {
+                    first: *first,
+                    second: op,
+                    location: location.clone(),
+                });
+            }
+        }
+        let mut cmd = self.translate_expression(left);
+        let mut right_cmd = self.translate_expression(right);
+        let cmds = match op {
+            // `a || b` as `if a true else b`, where `b` is checked to be a bool.
+            BinaryOp::Or => {
+                let mut right_cmd = self.translate_bool(right_cmd, location.clone());
+                cmd.push_at(Cmd::JumpRelUnless(3), location);
+                cmd.push(Cmd::BoolConst(true));
+                cmd.push(Cmd::JumpRel(right_cmd.len() + 1));
+                cmd.append(&mut right_cmd);
+                return cmd;
+            }
+            // `a && b` as `if a b else false`, where `b` is checked to be a bool.
+            BinaryOp::And => {
+                let mut right_cmd = self.translate_bool(right_cmd, location.clone());
+                cmd.push_at(Cmd::JumpRelUnless(right_cmd.len() + 2), location);
+                cmd.append(&mut right_cmd);
+                cmd.push(Cmd::JumpRel(2));
+                cmd.push(Cmd::BoolConst(false));
+                return cmd;
             }
+            BinaryOp::Equal => vec![Cmd::Equal],
+            BinaryOp::NotEqual => vec![Cmd::Equal, Cmd::Not],
+            BinaryOp::GreaterThan => vec![Cmd::GreaterThan],
+            BinaryOp::LessThan => vec![Cmd::LessThan],
+            BinaryOp::NotGreaterThan => vec![Cmd::GreaterThan, Cmd::Not],
+            BinaryOp::NotLessThan => vec![Cmd::LessThan, Cmd::Not],
+            BinaryOp::Add => vec![Cmd::Add],
+            BinaryOp::Sub => vec![Cmd::Sub],
+            BinaryOp::Mul => vec![Cmd::Mul],
+            BinaryOp::Div => vec![Cmd::Div],
+            BinaryOp::Surplus => vec![Cmd::Surplus],
+        };
+        cmd.append(&mut right_cmd);
+        for c in cmds {
+            cmd.push_at(c, location.clone());
         }
         cmd
     }
 
-    fn translate_additive(&mut self, v: &Additive) -> Vec<Cmd> {
-        let mut cmd = self.translate_multitive(&v.left);
-        for right in &v.rights {
-            match right {
-                AdditiveRight::Add(r) => {
-                    cmd.append(&mut self.translate_multitive(&r));
-                    cmd.push(Cmd::Add);
-                }
-                AdditiveRight::Sub(r) => {
-                    cmd.append(&mut self.translate_multitive(&r));
-                    cmd.push(Cmd::Sub);
-                }
-            }
-        }
+    /// Evaluates to the result of `cmd`, failing unless it is a bool.
+    fn translate_bool(&self, mut cmd: Code, location: Location) -> Code {
+        cmd.push_at(Cmd::JumpRelUnless(3), location);
+        cmd.push(Cmd::BoolConst(true));
+        cmd.push(Cmd::JumpRel(2));
+        cmd.push(Cmd::BoolConst(false));
         cmd
     }
 
-    fn translate_multitive(&mut self, v: &Multitive) -> Vec<Cmd> {
-        let mut cmd = self.translate_operation(&v.left);
-        for right in &v.rights {
-            match right {
-                MultitiveRight::Mul(r) => {
-                    cmd.append(&mut self.translate_operation(&r));
-                    cmd.push(Cmd::Mul);
-                }
-                MultitiveRight::Div(r) => {
-                    cmd.append(&mut self.translate_operation(&r));
-                    cmd.push(Cmd::Div);
-                }
-                MultitiveRight::Surplus(r) => {
-                    cmd.append(&mut self.translate_operation(&r));
-                    cmd.push(Cmd::Surplus);
-                }
-            }
+    fn translate_unary(&mut self, op: UnaryOp, operand: &Expression, span: Span) -> Code {
+        let mut cmd = self.translate_expression(operand);
+        match op {
+            UnaryOp::Not => cmd.push_at(Cmd::Not, self.location(span)),
+            UnaryOp::Neg => cmd.push_at(Cmd::Neg, self.location(span)),
         }
         cmd
     }
 
-    fn translate_operation(&mut self, v: &Operation) -> Vec<Cmd> {
+    fn translate_operation(&mut self, v: &Operation) -> Code {
         let mut cmd = self.translate_primary(&v.left);
-        for right in &v.rights {
+        for (right, span) in &v.rights {
+            let location = self.location(*span);
             match right {
                 OperationRight::Access(name) => {
                     cmd.push(Cmd::StringConst(Rc::new(name.clone())));
-                    cmd.push(Cmd::Access);
+                    cmd.push_at(Cmd::Access, location);
                 }
                 OperationRight::Call(args) => {
                     for arg in args {
                         cmd.append(&mut self.translate_expression(arg));
                     }
-                    cmd.push(Cmd::Call(args.len()));
+                    cmd.push_at(Cmd::Call(args.len()), location);
                 }
                 OperationRight::Index(arg) => {
                     cmd.append(&mut self.translate_expression(arg));
-                    cmd.push(Cmd::Index);
+                    cmd.push_at(Cmd::Index, location);
                 }
             }
         }
         cmd
     }
 
-    fn translate_primary(&mut self, v: &Primary) -> Vec<Cmd> {
+    fn translate_primary(&mut self, v: &Primary) -> Code {
         match v {
-            Primary::Number(v) => vec![Cmd::NumberConst(*v)],
-            Primary::Null => vec![Cmd::NullConst],
-            Primary::String(s) => vec![Cmd::StringConst(Rc::new(s.clone()))],
-            Primary::Variable(name) => self.translate_identifier(name),
+            Primary::Number(v) => Code::from(vec![Cmd::NumberConst(*v)]),
+            Primary::Bool(b) => Code::from(vec![Cmd::BoolConst(*b)]),
+            Primary::Null => Code::from(vec![Cmd::NullConst]),
+            Primary::String(s) => Code::from(vec![Cmd::StringConst(Rc::new(s.clone()))]),
+            Primary::Variable(name, span) => self.translate_identifier(name, *span),
+            Primary::Import(path, span) => self.translate_import(path, *span),
             Primary::ImmediateBlock(statement) => self.translate(statement),
+            Primary::Group(expression) => self.translate_expression(expression),
             Primary::Function(arg_names, body) => {
-                let mut body_cmd = Vec::new();
+                let mut body_cmd = Code::new();
                 let mut map = HashMap::new();
                 for (id, arg) in arg_names.iter().enumerate() {
                     map.insert(arg.to_string(), id);
@@ -292,8 +549,8 @@ impl Translator {
                 body_cmd.append(&mut translator.translate_expression(body));
                 body_cmd.push(Cmd::Return);
 
-                let mut cmd = Vec::new();
-                cmd.push(Cmd::ConstructFunction(body_cmd.len()));
+                let mut cmd = Code::new();
+                cmd.push(Cmd::ConstructFunction(arg_names.len(), body_cmd.len()));
                 cmd.append(&mut body_cmd);
                 cmd
             }
@@ -308,7 +565,7 @@ impl Translator {
                 block.finalize()
             }
             Primary::List(items) => {
-                let mut cmd = Vec::new();
+                let mut cmd = Code::new();
                 for item in items {
                     cmd.append(&mut self.translate_expression(item));
                 }
@@ -319,19 +576,330 @@ impl Translator {
         }
     }
 
-    fn translate_identifier(&self, name: &str) -> Vec<Cmd> {
-        let (id, depth) = self
-            .get_bind(name)
-            .unwrap_or_else(|| panic!("could not find bind by \"{}\"", name));
-        let mut cmd = Vec::new();
-        cmd.push(Cmd::Load(id, depth));
+    fn translate_identifier(&self, name: &str, span: Span) -> Code {
+        match self.get_bind(name) {
+            Some((id, depth)) => {
+                let mut cmd = Code::new();
+                cmd.push_at(Cmd::Load(id, depth), self.location(span));
+                cmd
+            }
+            None => {
+                let names = self.env.names();
+                let suggestion = diagnostic::suggest(name, names).map(String::from);
+                self.errors.borrow_mut().push(Error::UnknownIdentifier {
+                    name: name.to_string(),
+                    suggestion,
+                    location: self.location(span),
+                });
+                // Keeps translating to report every unknown identifier at once; the program is discarded anyway.
+                Code::from(vec![Cmd::NullConst])
+            }
+        }
+    }
+
+    /// Loads the bind of the module, which was loaded along with the program.
+    fn translate_import(&self, path: &str, span: Span) -> Code {
+        let key = (self.source.name.clone(), path.to_string());
+        let bind = self
+            .imports
+            .borrow()
+            .get(&key)
+            .and_then(|name| self.get_bind(name));
+        match bind {
+            Some((id, depth)) => {
+                let mut cmd = Code::new();
+                cmd.push_at(Cmd::Load(id, depth), self.location(span));
+                cmd
+            }
+            None => {
+                let message = format!("cannot import `{}`: the module was not loaded", path);
+                let diagnostic = Diagnostic::new(message, self.location(span));
+                self.errors.borrow_mut().push(Error::Import(diagnostic));
+                Code::from(vec![Cmd::NullConst])
+            }
+        }
+    }
+
+    pub fn translate_value(&self, v: Value) -> Code {
+        Code::from(vec![Cmd::ValueConst(v)])
+    }
+
+    /// A function which runs `op` over its `arity` arguments.
+    pub fn translate_primitive(&self, arity: usize, op: Cmd) -> Code {
+        let mut body: Vec<_> = (0..arity).map(|i| Cmd::Load(i, 0)).collect();
+        body.push(op);
+        body.push(Cmd::Return);
+
+        let mut cmd = Code::from(vec![Cmd::ConstructFunction(arity, body.len())]);
+        cmd.append(&mut Code::from(body));
         cmd
     }
+}
 
-    pub fn translate_foreign<F>(&self, f: F) -> Vec<Cmd>
-    where
-        F: Fn(Vec<Value>) -> Value + 'static,
-    {
-        vec![Cmd::ConstructForeignFunction(ForeignFunction(Rc::new(f)))]
+#[test]
+fn test_unknown_identifiers() {
+    let source = Arc::new(Source::new("test", "fib: (n) => fibb(n) + m,\nfib(1)"));
+    let ast = crate::parser::parse(&source).unwrap();
+    let errors = get_cmd(source, &ast, Vec::new(), &Library::default())
+        .unwrap_err()
+        .0;
+    let names: Vec<_> = errors
+        .iter()
+        .filter_map(|e| match e {
+            Error::UnknownIdentifier {
+                name, suggestion, ..
+            } => Some((name.as_str(), suggestion.as_deref())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(names, vec![("fibb", Some("fib")), ("m", None)]);
+}
+
+/// Evaluates `text` without library directories, so that `SPCTR_PATH` doesn't affect tests.
+#[cfg(test)]
+pub(crate) fn eval(text: &str) -> anyhow::Result<Value> {
+    let source = Arc::new(Source::new("test", text));
+    let ast = crate::parser::parse(&source).unwrap();
+    crate::vm::run(get_cmd(source, &ast, Vec::new(), &Library::default()).unwrap())
+}
+
+/// A directory for the files written by a test, removed when the test ends.
+#[cfg(test)]
+struct TempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("spctr-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn write(&self, name: &str, contents: &str) -> std::path::PathBuf {
+        let path = self.0.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn test_logical() {
+    let cases = vec![
+        ("true && false", "false"),
+        ("true && true", "true"),
+        ("false || true", "true"),
+        ("false || false", "false"),
+        ("!true", "false"),
+        ("!!true", "true"),
+        ("!false && 1 = 1", "true"),
+        ("true || false && false", "true"),
+        ("false && [][0]", "false"),
+        ("true || [][0]", "true"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(eval(text).unwrap().to_string(), expected, "{}", text);
     }
+    assert!(eval("true && 1").is_err());
+    assert!(eval("1 || true").is_err());
+    assert!(eval("!1").is_err());
+}
+
+#[test]
+fn test_neg() {
+    let cases = vec![
+        ("x: 3, -x", "-3"),
+        ("a: 1, b: 2, -a * b", "-2"),
+        ("f: (x) => x * 2, -f(1)", "-2"),
+        ("b: {x: 4}, 1 - -b.x", "5"),
+        ("x: 2, --x", "2"),
+        ("-3 * 2", "-6"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(eval(text).unwrap().to_string(), expected, "{}", text);
+    }
+    assert!(eval("-\"a\"").is_err());
+}
+
+#[test]
+fn test_operators() {
+    let cases = vec![
+        ("1 = 1", "true"),
+        ("1 = 2", "false"),
+        ("1 != 2", "true"),
+        ("1 != 1", "false"),
+        ("2 > 1", "true"),
+        ("1 > 1", "false"),
+        ("1 < 2", "true"),
+        ("1 < 1", "false"),
+        ("1 >= 1", "true"),
+        ("1 >= 2", "false"),
+        ("1 <= 1", "true"),
+        ("2 <= 1", "false"),
+        ("1 + 2 >= 3", "true"),
+        ("1 <= 2 && 2 <= 3", "true"),
+        ("7 + 2", "9"),
+        ("7 - 2", "5"),
+        ("7 * 2", "14"),
+        ("7 / 2", "3.5"),
+        ("7 % 2", "1"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(eval(text).unwrap().to_string(), expected, "{}", text);
+    }
+}
+
+#[test]
+fn test_chained_comparison() {
+    let source = Arc::new(Source::new("test", "a: 1, 0 < a <= 2"));
+    let ast = crate::parser::parse(&source).unwrap();
+    let errors = get_cmd(source, &ast, Vec::new(), &Library::default())
+        .unwrap_err()
+        .0;
+    assert_eq!(
+        errors[0].to_string(),
+        "comparison operators cannot be chained
+ --> test:1:13
+  |
+1 | a: 1, 0 < a <= 2
+  |             ^^
+  = help: combine the comparisons with `&&`, as in `a < b && b <= c`"
+    );
+}
+
+#[test]
+fn test_display() {
+    let cases = vec![
+        ("{a: 1, b: \"x\"}", "{a: 1, b: \"x\"}"),
+        ("{z: 1, a: 2, m: 3}", "{z: 1, a: 2, m: 3}"),
+        ("{}", "{}"),
+        ("{a: 1 + 1, b: {c: [a, {}]}}", "{a: 2, b: {c: [2, {}]}}"),
+        ("a: {me: a, n: 1}, a", "{me: {...}, n: 1}"),
+        (
+            "x: {b: {c: x}}, [x, x]",
+            "[{b: {c: {...}}}, {b: {c: {...}}}]",
+        ),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(eval(text).unwrap().to_string(), expected, "{}", text);
+    }
+}
+
+#[test]
+fn test_import_json() {
+    let dir = TempDir::new("import-json");
+    let path = dir.write(
+        "base.json",
+        r#"{"name": "web", "replicas": 2, "tags": ["a"]}"#,
+    );
+    let path = path.to_str().unwrap().replace('\\', "\\\\");
+    let text = format!(
+        "base: import_json(\"{}\"), {{name: base.name, replicas: base.replicas * 2, tags: base.tags, same: base = import_json(\"{}\")}}",
+        path, path
+    );
+    assert_eq!(
+        eval(&text).unwrap().to_string(),
+        "{name: \"web\", replicas: 4, tags: [\"a\"], same: true}"
+    );
+
+    let e = eval("import_json(\"no/such.json\")")
+        .unwrap_err()
+        .downcast::<crate::vm::Error>()
+        .unwrap();
+    assert!(e.message.starts_with("cannot read `no/such.json`: "));
+    assert_eq!(e.location.unwrap().to_string(), "test:1:1");
+
+    // Relative paths are resolved against the directory of the calling source.
+    dir.write("data.json", "[1, 2]");
+    let data = dir.write("data.spc", "import_json(\"data.json\")");
+    let data = data.to_str().unwrap().replace('\\', "\\\\");
+    assert_eq!(
+        eval(&format!("import \"{}\"", data)).unwrap().to_string(),
+        "[1, 2]"
+    );
+}
+
+#[test]
+fn test_import() {
+    assert_eq!(
+        eval("import \"examples/modules/main.spc\"")
+            .unwrap()
+            .to_string(),
+        "{greeting: \"Hello, spctr\", replicas: 4}"
+    );
+
+    let dir = TempDir::new("import");
+    let c = dir.write("c.spc", "import \"d.spc\"");
+    dir.write("d.spc", "1");
+    let c = c.to_str().unwrap().replace('\\', "\\\\");
+    assert_eq!(eval(&format!("import \"{}\"", c)).unwrap().to_string(), "1");
+
+    let a = dir.write("a.spc", "{b: import \"./b.spc\"}");
+    let b = dir.write("b.spc", "import \"./a.spc\"");
+    let a = a.to_str().unwrap().replace('\\', "\\\\");
+    let source = Arc::new(Source::new("test", format!("import \"{}\"", a)));
+    let ast = crate::parser::parse(&source).unwrap();
+    let e = get_cmd(source, &ast, Vec::new(), &Library::default())
+        .unwrap_err()
+        .0
+        .remove(0);
+    let diagnostic = e.diagnostic();
+    let a = dir.0.join("a.spc").display().to_string();
+    let b = b.display().to_string();
+    assert_eq!(
+        diagnostic.message,
+        format!("import cycle: `{}` -> `{}` -> `{}`", a, b, a)
+    );
+    assert_eq!(diagnostic.location.source.name, b);
+    assert_eq!(
+        diagnostic.notes,
+        vec![
+            format!("note: imported at {}:1:5", a),
+            "note: imported at test:1:1".to_string()
+        ]
+    );
+}
+
+#[test]
+fn test_library() {
+    let library = Library {
+        dirs: vec!["examples/modules/lib".into()],
+    };
+    let translate = |text: &str| {
+        let source = Arc::new(Source::new("test", text));
+        let ast = crate::parser::parse(&source).unwrap();
+        get_cmd(source, &ast, Vec::new(), &library)
+    };
+    let value = crate::vm::run(translate(
+        "[Strings.greet(\"a\"), (import \"strings\").greet(\"b\"), Strings = import \"strings.spc\"]",
+    ).unwrap());
+    assert_eq!(
+        value.unwrap().to_string(),
+        "[\"Hello, a\", \"Hello, b\", true]"
+    );
+
+    // A module of the prelude imported by the program is loaded once.
+    let source = Arc::new(Source::new("test", "import \"strings\""));
+    let ast = crate::parser::parse(&source).unwrap();
+    let mut loader = Loader::new(library.clone());
+    let prelude = loader.load_prelude().unwrap();
+    let modules = loader
+        .load(&source, &ast.definitions, Some(&ast.body))
+        .unwrap();
+    assert_eq!(prelude.modules.len(), 3);
+    assert!(modules.modules.is_empty());
+    assert_eq!(modules.imports.len(), 1);
+
+    let e = translate("import \"nope\"").unwrap_err().0.remove(0);
+    assert_eq!(
+        e.diagnostic().message,
+        "cannot import `nope`: no `nope` next to the importer, and no `nope.spc` in the library path"
+    );
 }
@@ -1,8 +1,13 @@
+use crate::diagnostic::{self, Diagnostic, Location};
+use crate::json;
 use anyhow::{anyhow, Result};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::error;
 use std::fmt;
 use std::mem;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 #[derive(Clone, Debug)]
@@ -15,17 +20,21 @@ pub enum Cmd {
     Equal,
     GreaterThan,
     LessThan,
+    Compare,
+    ImportJson,
     Not,
+    Neg,
     Load(usize, usize),
     Store(usize),
     Block(Vec<usize>),
     NumberConst(f64),
     StringConst(Rc<String>),
+    BoolConst(bool),
     NullConst,
+    ValueConst(Value),
     ConstructList(usize),
-    ConstructFunction(usize),
-    ConstructBlock(usize, Rc<HashMap<String, usize>>),
-    ConstructForeignFunction(ForeignFunction),
+    ConstructFunction(usize, usize),
+    ConstructBlock(Rc<HashMap<String, usize>>),
     JumpRel(usize),
     JumpRelUnless(usize),
     Call(usize),
@@ -35,8 +44,51 @@ pub enum Cmd {
     Return,
 }
 
+/// Commands along with the location each of them was translated from.
+#[derive(Clone, Debug, Default)]
+pub struct Code {
+    cmds: Vec<Cmd>,
+    locations: Vec<Option<Location>>,
+}
+
+impl Code {
+    pub fn new() -> Code {
+        Code::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cmds.len()
+    }
+
+    pub fn push(&mut self, cmd: Cmd) {
+        self.cmds.push(cmd);
+        self.locations.push(None);
+    }
+
+    pub fn push_at(&mut self, cmd: Cmd, location: Location) {
+        self.cmds.push(cmd);
+        self.locations.push(Some(location));
+    }
+
+    pub fn append(&mut self, other: &mut Code) {
+        self.cmds.append(&mut other.cmds);
+        self.locations.append(&mut other.locations);
+    }
+}
+
+impl From<Vec<Cmd>> for Code {
+    fn from(cmds: Vec<Cmd>) -> Code {
+        let locations = vec![None; cmds.len()];
+        Code { cmds, locations }
+    }
+}
+
+pub type ForeignFn = dyn Fn(&mut VM, Vec<Value>) -> Result<Value>;
+
+/// A function implemented in Rust, which gets the VM to call back into spctr functions.
+/// Its errors are reported as runtime errors at the call site.
 #[derive(Clone)]
-pub struct ForeignFunction(pub Rc<dyn Fn(Vec<Value>) -> Value>);
+pub struct ForeignFunction(pub Rc<ForeignFn>);
 
 impl fmt::Debug for ForeignFunction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -44,37 +96,78 @@ impl fmt::Debug for ForeignFunction {
     }
 }
 
+/// Escapes `s` so that it reads back as the same string literal.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\0' => escaped.push_str("\\0"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Number(n) => write!(f, "{}", n),
-            Value::String(ref s) => write!(f, "\"{}\"", s),
-            Value::Bool(b) => write!(f, "{}", b),
-            Value::Function(_) => write!(f, "[function]"),
-            Value::List(ref v) => {
-                let fmt_values: Vec<_> = v.iter().map(|v| format!("{}", v)).collect();
-                write!(f, "[{}]", fmt_values.join(", "))
+        write_value(f, self, &mut Vec::new())
+    }
+}
+
+/// Writes `value` with the fields of blocks in declaration order.
+/// Fields which are not evaluated yet are written as `<lazy>`,
+/// and blocks met again within themselves as `{...}`.
+fn write_value(
+    f: &mut fmt::Formatter<'_>,
+    value: &Value,
+    enclosing: &mut Vec<ScopeId>,
+) -> fmt::Result {
+    match value {
+        Value::Number(n) => write!(f, "{}", n),
+        Value::String(ref s) => write!(f, "\"{}\"", escape(s)),
+        Value::Bool(b) => write!(f, "{}", b),
+        Value::Function(_) => write!(f, "[function]"),
+        Value::List(ref v) => {
+            write!(f, "[")?;
+            for (i, v) in v.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_value(f, v, enclosing)?;
             }
-            Value::Null => write!(f, "null"),
-            Value::Block((_, field, _)) => {
-                write!(f, "{:?}", field)
-                // let mut vm = VM::new();
-                // vm.scope = self.scope.clone();
-                // let fmt_entries: Vec<_> = self
-                //     .field
-                //     .iter()
-                //     .map(|(k, v)| {
-                //         let v = vm.run(&[Cmd::Load(*v, 0)]).unwrap();
-                //         format!("{}: {}", k, v)
-                //     })
-                //     .collect();
-                // write!(f, "{{{}}}", fmt_entries.join(", "))
+            write!(f, "]")
+        }
+        Value::Null => write!(f, "null"),
+        Value::Block((map, scope)) => {
+            if enclosing.contains(&scope.id()) {
+                return write!(f, "{{...}}");
             }
+            enclosing.push(scope.id());
+            write!(f, "{{")?;
+            for (i, (name, n)) in fields(map).into_iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: ", name)?;
+                match &*scope.bind(n).borrow() {
+                    Bind::Evalueated(v) => write_value(f, v, enclosing)?,
+                    Bind::Cmd(_) => write!(f, "<lazy>")?,
+                }
+            }
+            enclosing.pop();
+            write!(f, "}}")
         }
     }
 }
 
-type Block = (usize, Rc<HashMap<String, usize>>, Scope);
+/// Names of the fields along with their ids, which are the indices of their binds in the innermost frame of the scope.
+type Block = (Rc<HashMap<String, usize>>, Scope);
 
 #[derive(Clone, Debug)]
 pub enum Value {
@@ -87,23 +180,26 @@ pub enum Value {
     Block(Block),
 }
 
-impl PartialEq for Value {
-    fn eq(&self, other: &Self) -> bool {
-        use Value::*;
+#[derive(Clone)]
+pub enum Function {
+    /// Address of the body, arity and the scope the function was created in.
+    Native(usize, usize, Scope),
+    Foreign(ForeignFunction),
+}
+
+impl Function {
+    /// Whether both are the same function, created by the same code in the same scope.
+    fn is(&self, other: &Function) -> bool {
         match (self, other) {
-            (Number(a), Number(b)) => (a - b).abs() < f64::EPSILON,
-            (Null, Null) => true,
+            (Function::Native(a, _, a_scope), Function::Native(b, _, b_scope)) => {
+                a == b && a_scope.id() == b_scope.id()
+            }
+            (Function::Foreign(a), Function::Foreign(b)) => Rc::ptr_eq(&a.0, &b.0),
             _ => false,
         }
     }
 }
 
-#[derive(Clone)]
-pub enum Function {
-    Native(usize, Scope),
-    Foreign(ForeignFunction),
-}
-
 impl fmt::Debug for Function {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "[function]")
@@ -111,6 +207,22 @@ impl fmt::Debug for Function {
 }
 
 impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Function(_) => "function",
+            Value::List(_) => "list",
+            Value::Null => "null",
+            Value::Block(_) => "block",
+        }
+    }
+
+    fn expected(&self, type_name: &str) -> anyhow::Error {
+        anyhow!("expected {}, found {}", type_name, self.type_name())
+    }
+
     pub fn number(f: f64) -> Value {
         Value::Number(f)
     }
@@ -122,7 +234,7 @@ impl Value {
     pub fn into_number(self) -> Result<f64> {
         match self {
             Value::Number(n) => Ok(n),
-            _ => Err(anyhow!("not number")),
+            _ => Err(self.expected("number")),
         }
     }
 
@@ -133,7 +245,7 @@ impl Value {
     pub fn into_bool(self) -> Result<bool> {
         match self {
             Value::Bool(b) => Ok(b),
-            _ => Err(anyhow!("not bool")),
+            _ => Err(self.expected("bool")),
         }
     }
 
@@ -141,13 +253,6 @@ impl Value {
         Value::Function(f)
     }
 
-    pub fn into_function(self) -> Result<Function> {
-        match self {
-            Value::Function(func) => Ok(func),
-            _ => Err(anyhow!("{:?} is not function", self)),
-        }
-    }
-
     pub fn string(v: Rc<String>) -> Value {
         Value::String(v)
     }
@@ -155,19 +260,31 @@ impl Value {
     pub fn into_string(self) -> Result<Rc<String>> {
         match self {
             Value::String(s) => Ok(s),
-            _ => Err(anyhow!("{:?} is not string", self)),
+            _ => Err(self.expected("string")),
         }
     }
 
-    pub fn block(i: usize, field: Rc<HashMap<String, usize>>, scope: Scope) -> Value {
-        Value::Block((i, field, scope))
+    pub fn block(field: Rc<HashMap<String, usize>>, scope: Scope) -> Value {
+        Value::Block((field, scope))
     }
 
-    pub fn into_block(self) -> Result<Block> {
-        match self {
-            Value::Block(b) => Ok(b),
-            _ => Err(anyhow!("{:?} is not block", self)),
+    /// A block whose fields are evaluated already. A field replaces any earlier one of the same name.
+    pub fn from_fields(fields: Vec<(String, Value)>) -> Value {
+        let mut map = HashMap::new();
+        let mut binds: Binds = Vec::new();
+        for (name, value) in fields {
+            let bind = Rc::new(RefCell::new(Bind::Evalueated(value)));
+            match map.get(&name) {
+                Some(&n) => binds[n] = bind,
+                None => {
+                    map.insert(name, binds.len());
+                    binds.push(bind);
+                }
+            }
         }
+        let mut scope = Scope(None);
+        scope.push(binds);
+        Value::Block((Rc::new(map), scope))
     }
 
     pub fn list(v: Rc<Vec<Value>>) -> Value {
@@ -177,22 +294,123 @@ impl Value {
     pub fn into_list(self) -> Result<Rc<Vec<Value>>> {
         match self {
             Value::List(v) => Ok(v),
-            _ => Err(anyhow!("{:?} is not list", self)),
+            _ => Err(self.expected("list")),
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// The value of the field `name` of a block, if it has the field and it is evaluated.
+    /// Results of `Engine` are evaluated in full, but the arguments of host functions are not;
+    /// those get their fields with `Caller::get`.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let (map, scope) = match self {
+            Value::Block(block) => block,
+            _ => return None,
+        };
+        let bind = scope.bind(*map.get(name)?);
+        let bind = bind.borrow();
+        match &*bind {
+            Bind::Evalueated(v) => Some(v.clone()),
+            Bind::Cmd(_) => None,
+        }
+    }
+
+    /// The fields of a block in declaration order. The block must have been forced beforehand.
+    pub fn into_fields(self) -> Result<Vec<(String, Value)>> {
+        let (map, scope) = match self {
+            Value::Block(block) => block,
+            _ => return Err(self.expected("block")),
+        };
+        fields(&map)
+            .into_iter()
+            .map(|(name, n)| match &*scope.bind(n).borrow() {
+                Bind::Evalueated(v) => Ok((name.clone(), v.clone())),
+                Bind::Cmd(_) => Err(anyhow!("field `{}` is not evaluated", name)),
+            })
+            .collect()
+    }
+
+    /// Whether both are the same block or function, as opposed to equal ones.
+    pub fn is(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Block((_, a)), Value::Block((_, b))) => a.id() == b.id(),
+            (Value::Function(a), Value::Function(b)) => a.is(b),
+            _ => false,
+        }
+    }
+
+    /// Orders numbers, strings, and lists of them element by element.
+    pub fn compare(&self, other: &Value) -> Result<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a
+                .partial_cmp(b)
+                .ok_or_else(|| anyhow!("cannot compare NaN")),
+            (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+            (Value::List(a), Value::List(b)) => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    match a.compare(b)? {
+                        Ordering::Equal => {}
+                        ordering => return Ok(ordering),
+                    }
+                }
+                Ok(a.len().cmp(&b.len()))
+            }
+            (a, b) if a.type_name() == b.type_name() => {
+                Err(anyhow!("cannot compare {} values", a.type_name()))
+            }
+            (a, b) => Err(anyhow!(
+                "cannot compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            )),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum Bind {
     Cmd(usize),
     Evalueated(Value),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Scope(Option<Rc<(Binds, Scope)>>);
 
 type Binds = Vec<Rc<RefCell<Bind>>>;
 
+type ScopeId = Option<*const (Binds, Scope)>;
+
 impl Scope {
     fn push(&mut self, binds: Binds) {
         self.0 = Some(Rc::new((binds, Scope(self.0.take()))));
@@ -205,6 +423,15 @@ impl Scope {
         head
     }
 
+    fn bind(&self, n: usize) -> Rc<RefCell<Bind>> {
+        self.0.as_ref().unwrap().0[n].clone()
+    }
+
+    /// Identifies the innermost frame, which is shared by the clones of the scope.
+    fn id(&self) -> Option<*const (Binds, Scope)> {
+        self.0.as_ref().map(Rc::as_ptr)
+    }
+
     fn nth_parent(&self, n: usize) -> &Scope {
         if n == 0 {
             return self;
@@ -214,134 +441,443 @@ impl Scope {
     }
 }
 
-pub fn run(program: &[Cmd]) -> Result<Value> {
-    let vm = VM::new(program);
-    vm.run()
+/// A frame of the spctr-level call stack, found at the command which entered it.
+#[derive(Debug)]
+pub struct Frame {
+    pub kind: &'static str,
+    pub location: Location,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = &self.location.source.text[self.location.start..self.location.end];
+        let snippet = text.lines().next().unwrap_or_default();
+        let ellipsis = if snippet.len() < text.len() {
+            " ..."
+        } else {
+            ""
+        };
+        write!(
+            f,
+            "in {} `{}{}` at {}",
+            self.kind, snippet, ellipsis, self.location
+        )
+    }
+}
+
+const MAX_TRACE: usize = 16;
+
+#[derive(Debug)]
+pub struct Error {
+    pub message: String,
+    pub location: Option<Location>,
+    /// Innermost frame first.
+    pub trace: Vec<Frame>,
 }
 
-struct VM<'a> {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}", Diagnostic::new(&self.message, location.clone()))?,
+            None => write!(f, "{}", self.message)?,
+        }
+        for frame in self.trace.iter().take(MAX_TRACE) {
+            write!(f, "\n  {}", frame)?;
+        }
+        if self.trace.len() > MAX_TRACE {
+            write!(f, "\n  ... and {} more", self.trace.len() - MAX_TRACE)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Error {}
+
+/// The fields of a block in declaration order.
+fn fields(map: &HashMap<String, usize>) -> Vec<(&String, usize)> {
+    let mut fields: Vec<_> = map.iter().map(|(name, id)| (name, *id)).collect();
+    fields.sort_by_key(|(_, id)| *id);
+    fields
+}
+
+pub(crate) fn arity_error(arity: usize, given: usize) -> anyhow::Error {
+    anyhow!(
+        "function takes {} argument{} but {} {} given",
+        arity,
+        if arity == 1 { "" } else { "s" },
+        given,
+        if given == 1 { "was" } else { "were" }
+    )
+}
+
+/// Runs `program` and evaluates the result in full.
+pub fn run(program: Code) -> Result<Value> {
+    VM::new().run(program)
+}
+
+pub struct VM {
     scope: Scope,
     call_stack: Vec<(usize, Scope)>,
     stack: Vec<Value>,
     i: usize,
-    program: &'a [Cmd],
+    program: Rc<Code>,
 }
 
-impl<'a> VM<'a> {
-    fn new(program: &'a [Cmd]) -> VM {
+impl VM {
+    pub fn new() -> VM {
         let scope: Scope = Scope(None);
         VM {
             scope,
             call_stack: Vec::new(),
             stack: Vec::new(),
             i: 0,
-            program,
-        }
-    }
-
-    fn run(mut self) -> Result<Value> {
-        let len = self.program.len();
-        while len > self.i {
-            use Cmd::*;
-            match self.program[self.i] {
-                Add => self.add()?,
-                Sub => self.sub()?,
-                Mul => self.mul()?,
-                Div => self.div()?,
-                Surplus => self.surplus()?,
-                Equal => self.equal()?,
-                Not => self.not()?,
-                GreaterThan => self.greater_than()?,
-                LessThan => self.less_than()?,
-                NumberConst(n) => self.number_const(n)?,
-                StringConst(ref s) => self.string_const(s.clone())?,
-                ConstructList(size) => self.list(size)?,
-                NullConst => self.null()?,
-                Block(ref def_addrs) => self.block(def_addrs)?,
-                Return => self.return_()?,
-                ExitScope => self.exit_scope()?,
-                JumpRel(n) => self.jump_rel(n)?,
-                JumpRelUnless(n) => self.jump_rel_unless(n)?,
-                Load(i, depth) => self.load(i, depth)?,
-                Store(i) => self.store(i)?,
-                ConstructFunction(len) => self.function(len)?,
-                ConstructForeignFunction(ref func) => self.foreign_function(func.clone())?,
-                ConstructBlock(len, ref map) => self.construct_block(len, map.clone())?,
-                Call(arg_len) => self.call(arg_len)?,
-                Access => self.access()?,
-                Index => self.index()?,
-            };
+            program: Rc::new(Code::new()),
         }
-        Ok(self.stack.pop().unwrap())
     }
 
-    fn add(&mut self) -> Result<()> {
-        let r = self.stack.pop().unwrap().into_number()?;
-        let l = self.stack.pop().unwrap().into_number()?;
-        self.stack.push(Value::number(l + r));
-        self.i += 1;
+    /// Appends `program` to the loaded code and executes it.
+    /// Scopes opened by the code stay open, so later calls can refer to their binds.
+    /// On failure the VM is rolled back to the state before the call.
+    pub fn execute(&mut self, mut program: Code) -> Result<()> {
+        self.i = self.program.len();
+        Rc::make_mut(&mut self.program).append(&mut program);
+        self.rolling_back(VM::execute_loaded)
+    }
+
+    /// Executes `program` and evaluates its result in full.
+    /// If either fails, the VM is rolled back to the state before the call.
+    pub fn run(&mut self, mut program: Code) -> Result<Value> {
+        self.i = self.program.len();
+        Rc::make_mut(&mut self.program).append(&mut program);
+        self.rolling_back(|vm| {
+            vm.execute_loaded()?;
+            let value = vm.pop()?;
+            vm.force_fields(&value, &mut HashSet::new())?;
+            Ok(value)
+        })
+    }
+
+    /// Evaluates every field of the blocks within `value`, so that it can be displayed in full.
+    pub fn force(&mut self, value: &Value) -> Result<()> {
+        self.rolling_back(|vm| vm.force_fields(value, &mut HashSet::new()))
+    }
+
+    fn force_fields(&mut self, value: &Value, forced: &mut HashSet<ScopeId>) -> Result<()> {
+        match value {
+            Value::List(values) => {
+                for value in values.iter() {
+                    self.force_fields(value, forced)?;
+                }
+            }
+            Value::Block((map, scope)) => {
+                if !forced.insert(scope.id()) {
+                    return Ok(());
+                }
+                for (_, n) in fields(map) {
+                    let value = self.force_bind(scope, n)?;
+                    self.force_fields(&value, forced)?;
+                }
+            }
+            _ => {}
+        }
         Ok(())
     }
 
-    fn sub(&mut self) -> Result<()> {
-        let r = self.stack.pop().unwrap().into_number()?;
-        let l = self.stack.pop().unwrap().into_number()?;
-        self.stack.push(Value::number(l - r));
-        self.i += 1;
+    /// Runs `f`, restoring the state of the VM from before the call if it fails.
+    fn rolling_back<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut VM) -> Result<T>,
+    {
+        let scope = self.scope.clone();
+        let result = f(self);
+        if result.is_err() {
+            self.scope = scope;
+            self.call_stack.clear();
+            self.stack.clear();
+            self.i = self.program.len();
+        }
+        result
+    }
+
+    fn execute_loaded(&mut self) -> Result<()> {
+        let program = self.program.clone();
+        while program.len() > self.i {
+            self.step(&program)?;
+        }
         Ok(())
     }
 
-    fn mul(&mut self) -> Result<()> {
-        let r = self.stack.pop().unwrap().into_number()?;
-        let l = self.stack.pop().unwrap().into_number()?;
-        self.stack.push(Value::number(l * r));
-        self.i += 1;
+    /// Runs until the frames above `depth` return.
+    fn execute_until(&mut self, depth: usize) -> Result<()> {
+        let program = self.program.clone();
+        while self.call_stack.len() > depth {
+            self.step(&program)?;
+        }
         Ok(())
     }
 
-    fn div(&mut self) -> Result<()> {
-        let r = self.stack.pop().unwrap().into_number()?;
-        let l = self.stack.pop().unwrap().into_number()?;
-        self.stack.push(Value::number(l / r));
+    fn step(&mut self, program: &Code) -> Result<()> {
+        use Cmd::*;
+        let result = match program.cmds[self.i] {
+            Add => self.add(),
+            Sub => self.sub(),
+            Mul => self.mul(),
+            Div => self.div(),
+            Surplus => self.surplus(),
+            Equal => self.equal(),
+            Not => self.not(),
+            Neg => self.neg(),
+            GreaterThan => self.greater_than(),
+            LessThan => self.less_than(),
+            Compare => self.compare(),
+            ImportJson => self.import_json(),
+            NumberConst(n) => self.number_const(n),
+            StringConst(ref s) => self.string_const(s.clone()),
+            ConstructList(size) => self.list(size),
+            BoolConst(b) => self.bool_const(b),
+            NullConst => self.null(),
+            ValueConst(ref v) => self.value_const(v.clone()),
+            Block(ref def_addrs) => self.block(def_addrs),
+            Return => self.return_(),
+            ExitScope => self.exit_scope(),
+            JumpRel(n) => self.jump_rel(n),
+            JumpRelUnless(n) => self.jump_rel_unless(n),
+            Load(i, depth) => self.load(i, depth),
+            Store(i) => self.store(i),
+            ConstructFunction(arity, len) => self.function(arity, len),
+            ConstructBlock(ref map) => self.construct_block(map.clone()),
+            Call(arg_len) => self.call(arg_len),
+            Access => self.access(),
+            Index => self.index(),
+        };
+        // Errors raised by nested evaluations already carry their location.
+        result.map_err(|e| match e.downcast::<Error>() {
+            Ok(e) => e.into(),
+            Err(e) => self.error(e).into(),
+        })
+    }
+
+    /// Attaches the location of the current command and the call stack to `e`.
+    fn error(&self, e: anyhow::Error) -> Error {
+        let mut trace: Vec<_> = self
+            .call_stack
+            .iter()
+            .rev()
+            .filter_map(|(ret_i, _)| {
+                // Frames entered from outside the program, as when forcing a result, have no command.
+                let kind = match self.program.cmds.get(ret_i - 1)? {
+                    Cmd::Call(_) => "call",
+                    Cmd::Load(_, _) => "binding",
+                    Cmd::Access => "field",
+                    _ => return None,
+                };
+                let location = self.program.locations[ret_i - 1].clone()?;
+                Some(Frame { kind, location })
+            })
+            .collect();
+        // Commands without a location of their own, as in the prelude, are attributed to their caller.
+        let location = match self.program.locations.get(self.i).cloned().flatten() {
+            None if !trace.is_empty() => Some(trace.remove(0).location),
+            location => location,
+        };
+        Error {
+            message: e.to_string(),
+            location,
+            trace,
+        }
+    }
+
+    /// The location of the current command, or of the innermost call with one.
+    fn caller_location(&self) -> Option<&Location> {
+        let current = self.program.locations.get(self.i).and_then(Option::as_ref);
+        current.or_else(|| {
+            self.call_stack
+                .iter()
+                .rev()
+                .find_map(|(ret_i, _)| self.program.locations.get(ret_i - 1)?.as_ref())
+        })
+    }
+
+    fn pop(&mut self) -> Result<Value> {
+        self.stack.pop().ok_or_else(|| anyhow!("stack underflow"))
+    }
+
+    fn arithmetic<F>(&mut self, op: &str, f: F) -> Result<()>
+    where
+        F: Fn(f64, f64) -> Value,
+    {
+        let r = self.pop()?;
+        let l = self.pop()?;
+        match (l, r) {
+            (Value::Number(l), Value::Number(r)) => self.stack.push(f(l, r)),
+            (l, r) => {
+                return Err(anyhow!(
+                    "cannot apply `{}` to {} and {}",
+                    op,
+                    l.type_name(),
+                    r.type_name()
+                ))
+            }
+        }
         self.i += 1;
         Ok(())
     }
 
+    fn add(&mut self) -> Result<()> {
+        self.arithmetic("+", |l, r| Value::number(l + r))
+    }
+
+    fn sub(&mut self) -> Result<()> {
+        self.arithmetic("-", |l, r| Value::number(l - r))
+    }
+
+    fn mul(&mut self) -> Result<()> {
+        self.arithmetic("*", |l, r| Value::number(l * r))
+    }
+
+    fn div(&mut self) -> Result<()> {
+        self.arithmetic("/", |l, r| Value::number(l / r))
+    }
+
     fn surplus(&mut self) -> Result<()> {
-        let r = self.stack.pop().unwrap().into_number()?;
-        let l = self.stack.pop().unwrap().into_number()?;
-        self.stack.push(Value::number(l % r));
-        self.i += 1;
-        Ok(())
+        self.arithmetic("%", |l, r| Value::number(l % r))
     }
 
     fn equal(&mut self) -> Result<()> {
-        let r = self.stack.pop().unwrap();
-        let l = self.stack.pop().unwrap();
-        self.stack.push(Value::bool(r == l));
+        let r = self.pop()?;
+        let l = self.pop()?;
+        let eq = self.equals(&l, &r, &mut Vec::new())?;
+        self.stack.push(Value::bool(eq));
         self.i += 1;
         Ok(())
     }
 
+    /// Deep structural equality, evaluating the fields of blocks as needed.
+    /// Numbers compare exactly and functions are only equal to themselves.
+    /// `assumed` holds the pairs of blocks being compared, which are taken as equal when met again
+    /// so that self-referential blocks compare in finite time.
+    fn equals(
+        &mut self,
+        l: &Value,
+        r: &Value,
+        assumed: &mut Vec<(ScopeId, ScopeId)>,
+    ) -> Result<bool> {
+        match (l, r) {
+            (Value::Number(a), Value::Number(b)) => Ok(a == b),
+            (Value::Bool(a), Value::Bool(b)) => Ok(a == b),
+            (Value::String(a), Value::String(b)) => Ok(a == b),
+            (Value::Null, Value::Null) => Ok(true),
+            (Value::Function(a), Value::Function(b)) => Ok(a.is(b)),
+            (Value::List(a), Value::List(b)) => {
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                for (a, b) in a.iter().zip(b.iter()) {
+                    if !self.equals(a, b, assumed)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (Value::Block((a_map, a_scope)), Value::Block((b_map, b_scope))) => {
+                let pair = (a_scope.id(), b_scope.id());
+                if pair.0 == pair.1 || assumed.contains(&pair) {
+                    return Ok(true);
+                }
+                if a_map.len() != b_map.len() {
+                    return Ok(false);
+                }
+                assumed.push(pair);
+                let eq = self.fields_equal(a_map, a_scope, b_map, b_scope, assumed);
+                assumed.pop();
+                eq
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn fields_equal(
+        &mut self,
+        a_map: &HashMap<String, usize>,
+        a_scope: &Scope,
+        b_map: &HashMap<String, usize>,
+        b_scope: &Scope,
+        assumed: &mut Vec<(ScopeId, ScopeId)>,
+    ) -> Result<bool> {
+        for (name, a_id) in fields(a_map) {
+            let b_id = match b_map.get(name) {
+                Some(id) => *id,
+                None => return Ok(false),
+            };
+            let a = self.force_bind(a_scope, a_id)?;
+            let b = self.force_bind(b_scope, b_id)?;
+            if !self.equals(&a, &b, assumed)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
     fn not(&mut self) -> Result<()> {
-        let b = self.stack.pop().unwrap().into_bool()?;
+        let b = self.pop()?.into_bool()?;
         self.stack.push(Value::bool(!b));
         self.i += 1;
         Ok(())
     }
 
-    fn greater_than(&mut self) -> Result<()> {
-        let r = self.stack.pop().unwrap().into_number()?;
-        let l = self.stack.pop().unwrap().into_number()?;
-        self.stack.push(Value::bool(l > r));
+    fn neg(&mut self) -> Result<()> {
+        let v = self.pop()?;
+        match v {
+            Value::Number(n) => self.stack.push(Value::number(-n)),
+            _ => return Err(anyhow!("cannot apply `-` to {}", v.type_name())),
+        }
         self.i += 1;
         Ok(())
     }
 
+    /// Whether the left operand is ordered as `expected` against the right one.
+    /// Numbers compare as IEEE 754 floats, so that any comparison with NaN is false rather than an error.
+    fn relation(&mut self, expected: Ordering) -> Result<()> {
+        let r = self.pop()?;
+        let l = self.pop()?;
+        let b = match (&l, &r) {
+            (Value::Number(l), Value::Number(r)) => l.partial_cmp(r) == Some(expected),
+            _ => l.compare(&r)? == expected,
+        };
+        self.stack.push(Value::bool(b));
+        self.i += 1;
+        Ok(())
+    }
+
+    fn greater_than(&mut self) -> Result<()> {
+        self.relation(Ordering::Greater)
+    }
+
     fn less_than(&mut self) -> Result<()> {
-        let r = self.stack.pop().unwrap().into_number()?;
-        let l = self.stack.pop().unwrap().into_number()?;
-        self.stack.push(Value::bool(l < r));
+        self.relation(Ordering::Less)
+    }
+
+    fn compare(&mut self) -> Result<()> {
+        let r = self.pop()?;
+        let l = self.pop()?;
+        let ordering = l.compare(&r)?;
+        self.stack.push(Value::number(ordering as i8 as f64));
+        self.i += 1;
+        Ok(())
+    }
+
+    /// Reads a JSON file, resolving relative paths against the directory of the calling source,
+    /// as `import` does.
+    fn import_json(&mut self) -> Result<()> {
+        let path = self.pop()?.into_string()?;
+        let path = match self.caller_location() {
+            Some(location) => Path::new(&location.source.name)
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(&*path),
+            None => PathBuf::from(&*path),
+        };
+        self.stack.push(json::read_file(&path.to_string_lossy())?);
         self.i += 1;
         Ok(())
     }
@@ -358,10 +894,16 @@ impl<'a> VM<'a> {
         Ok(())
     }
 
+    fn value_const(&mut self, v: Value) -> Result<()> {
+        self.stack.push(v);
+        self.i += 1;
+        Ok(())
+    }
+
     fn list(&mut self, size: usize) -> Result<()> {
         let mut vec = Vec::new();
         for _ in 0..size {
-            let v = self.stack.pop().unwrap();
+            let v = self.pop()?;
             vec.push(v);
         }
         vec.reverse();
@@ -370,6 +912,12 @@ impl<'a> VM<'a> {
         Ok(())
     }
 
+    fn bool_const(&mut self, b: bool) -> Result<()> {
+        self.stack.push(Value::bool(b));
+        self.i += 1;
+        Ok(())
+    }
+
     fn null(&mut self) -> Result<()> {
         self.stack.push(Value::null());
         self.i += 1;
@@ -402,7 +950,16 @@ impl<'a> VM<'a> {
     }
 
     fn jump_rel_unless(&mut self, n: usize) -> Result<()> {
-        let cond = self.stack.pop().unwrap().into_bool()?;
+        let cond = self.pop()?;
+        let cond = match cond {
+            Value::Bool(b) => b,
+            _ => {
+                return Err(anyhow!(
+                    "condition must be bool, found {}",
+                    cond.type_name()
+                ))
+            }
+        };
         if !cond {
             self.i += n;
             return Ok(());
@@ -412,11 +969,13 @@ impl<'a> VM<'a> {
     }
 
     fn load(&mut self, n: usize, depth: usize) -> Result<()> {
-        let scope = self.scope.nth_parent(depth);
-        let binds: &Binds = scope.0.as_ref().unwrap().0.as_ref();
+        let scope = self.scope.nth_parent(depth).clone();
+        self.load_from(scope, n)
+    }
 
-        let bind = binds.get(n).unwrap();
-        let inner = bind.borrow().clone();
+    /// Pushes the value of the `n`th bind of `scope`, jumping into its code first if it is not evaluated yet.
+    fn load_from(&mut self, scope: Scope, n: usize) -> Result<()> {
+        let inner = scope.bind(n).borrow().clone();
         match inner {
             Bind::Evalueated(v) => {
                 self.stack.push(v);
@@ -424,17 +983,71 @@ impl<'a> VM<'a> {
                 Ok(())
             }
             Bind::Cmd(addr) => {
-                let ret_i = self.i + 1;
-                let scope = scope.clone();
                 let ret_scope = mem::replace(&mut self.scope, scope);
-
-                self.call_stack.push((ret_i, ret_scope));
+                self.call_stack.push((self.i + 1, ret_scope));
                 self.i = addr;
                 Ok(())
             }
         }
     }
 
+    /// Evaluates the `n`th bind of `scope` on the spot, running the VM until its code returns.
+    fn force_bind(&mut self, scope: &Scope, n: usize) -> Result<Value> {
+        let i = self.i;
+        let depth = self.call_stack.len();
+        self.load_from(scope.clone(), n)?;
+        self.execute_until(depth)?;
+        self.i = i;
+        self.pop()
+    }
+
+    /// Calls `f` with `args` on the spot, running the VM until it returns.
+    pub fn call_function(&mut self, f: Value, args: Vec<Value>) -> Result<Value> {
+        self.nested(|vm| {
+            let depth = vm.call_stack.len();
+            vm.stack.push(f);
+            let arg_len = args.len();
+            vm.stack.extend(args);
+            vm.call(arg_len)?;
+            vm.execute_until(depth)?;
+            vm.pop()
+        })
+    }
+
+    /// The value of the field `name` of a block, evaluating it on the spot if needed.
+    pub fn field(&mut self, value: &Value, name: &str) -> Result<Option<Value>> {
+        match value {
+            Value::Block((map, scope)) => match map.get(name) {
+                Some(&n) => self.nested(|vm| vm.force_bind(scope, n)).map(Some),
+                None => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Evaluates every field of the blocks within `value` from within a running command, as `force` does.
+    pub fn force_nested(&mut self, value: &Value) -> Result<()> {
+        self.nested(|vm| vm.force_fields(value, &mut HashSet::new()))
+    }
+
+    /// Runs `f` from within the current command.
+    /// On failure the VM is restored, so that the caller can carry on.
+    fn nested<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut VM) -> Result<T>,
+    {
+        let (i, scope) = (self.i, self.scope.clone());
+        let (depth, len) = (self.call_stack.len(), self.stack.len());
+        let result = f(self);
+        self.i = i;
+        if result.is_err() {
+            self.scope = scope;
+            self.call_stack.truncate(depth);
+            self.stack.truncate(len);
+        }
+        result
+    }
+
     fn return_(&mut self) -> Result<()> {
         let (ret_i, ret_scope) = self.call_stack.pop().unwrap();
         self.i = ret_i;
@@ -443,7 +1056,7 @@ impl<'a> VM<'a> {
     }
 
     fn store(&mut self, n: usize) -> Result<()> {
-        let v = self.stack.pop().unwrap();
+        let v = self.pop()?;
         let binds: &Binds = self.scope.0.as_ref().unwrap().0.as_ref();
 
         let bind = binds.get(n).unwrap();
@@ -454,35 +1067,37 @@ impl<'a> VM<'a> {
         Ok(())
     }
 
-    fn function(&mut self, len: usize) -> Result<()> {
+    fn function(&mut self, arity: usize, len: usize) -> Result<()> {
         let body_base = self.i + 1;
         self.stack.push(Value::function(Function::Native(
             body_base,
+            arity,
             self.scope.clone(),
         )));
         self.i = body_base + len;
         Ok(())
     }
 
-    fn foreign_function(&mut self, func: ForeignFunction) -> Result<()> {
-        self.stack.push(Value::function(Function::Foreign(func)));
+    fn construct_block(&mut self, map: Rc<HashMap<String, usize>>) -> Result<()> {
+        self.stack.push(Value::block(map, self.scope.clone()));
         self.i += 1;
         Ok(())
     }
 
-    fn construct_block(&mut self, len: usize, map: Rc<HashMap<String, usize>>) -> Result<()> {
-        self.stack
-            .push(Value::block(self.i + 1, map, self.scope.clone()));
-        self.i += 1 + len;
-        Ok(())
-    }
-
     fn call(&mut self, arg_len: usize) -> Result<()> {
         let len = self.stack.len() - arg_len;
-        let mut args = self.stack.split_off(len);
-
-        match self.stack.pop().unwrap().into_function()? {
-            Function::Native(addr, closure_scope) => {
+        let args = self.stack.split_off(len);
+
+        let f = self.pop()?;
+        let f = match f {
+            Value::Function(f) => f,
+            _ => return Err(anyhow!("cannot call {}", f.type_name())),
+        };
+        match f {
+            Function::Native(addr, arity, closure_scope) => {
+                if args.len() != arity {
+                    return Err(arity_error(arity, args.len()));
+                }
                 let mut defs = Vec::new();
                 for arg in args {
                     defs.push(Rc::new(RefCell::new(Bind::Evalueated(arg))));
@@ -496,8 +1111,8 @@ impl<'a> VM<'a> {
                 Ok(())
             }
             Function::Foreign(func) => {
-                args.reverse();
-                self.stack.push(func.0(args));
+                let value = func.0(self, args)?;
+                self.stack.push(value);
                 self.i += 1;
                 Ok(())
             }
@@ -505,22 +1120,188 @@ impl<'a> VM<'a> {
     }
 
     fn access(&mut self) -> Result<()> {
-        let name = self.stack.pop().unwrap().into_string()?;
-        let (addr, map, scope) = self.stack.pop().unwrap().into_block()?;
-        let id = map.get(&*name).unwrap();
-        let ret_scope = mem::replace(&mut self.scope, scope);
-
-        self.call_stack.push((self.i + 1, ret_scope));
-        self.i = id * 2 + addr;
-        Ok(())
+        let name = self.pop()?.into_string()?;
+        let target = self.pop()?;
+        let (map, scope) = match target {
+            Value::Block(b) => b,
+            _ => {
+                return Err(anyhow!(
+                    "cannot access field `{}` of {}",
+                    name,
+                    target.type_name()
+                ))
+            }
+        };
+        let id = match map.get(&*name) {
+            Some(id) => *id,
+            None => {
+                let suggestion = diagnostic::suggest(&name, map.keys().map(String::as_str))
+                    .map_or_else(String::new, |s| format!(", did you mean `{}`?", s));
+                return Err(anyhow!("block has no field `{}`{}", name, suggestion));
+            }
+        };
+        self.load_from(scope, id)
     }
 
     fn index(&mut self) -> Result<()> {
-        let index = self.stack.pop().unwrap().into_number()?;
-        let list = self.stack.pop().unwrap().into_list()?;
-        self.stack.push(list.get(index as usize).unwrap().clone());
+        let index = self.pop()?.into_number()?;
+        let list = self.pop()?.into_list()?;
+        if index < 0.0 || index.fract() != 0.0 {
+            return Err(anyhow!(
+                "index must be a non-negative integer, found {}",
+                index
+            ));
+        }
+        let v = list.get(index as usize).ok_or_else(|| {
+            anyhow!(
+                "index {} out of range for list of length {}",
+                index,
+                list.len()
+            )
+        })?;
+        self.stack.push(v.clone());
 
         self.i += 1;
         Ok(())
     }
 }
+
+#[test]
+fn test_runtime_error() {
+    use crate::{parser, translator};
+    use std::sync::Arc;
+
+    let source = Arc::new(crate::diagnostic::Source::new(
+        "test",
+        "f: (x) => if x = 0 \"a\" + 1 f(x - 1),\nf(1)",
+    ));
+    let ast = parser::parse(&source).unwrap();
+    let program =
+        translator::get_cmd(source, &ast, Vec::new(), &crate::module::Library::default()).unwrap();
+    let e = run(program).unwrap_err().downcast::<Error>().unwrap();
+    assert_eq!(e.message, "cannot apply `+` to string and number");
+    assert_eq!(e.location.unwrap().to_string(), "test:1:24");
+    let trace: Vec<_> = e.trace.iter().map(Frame::to_string).collect();
+    assert_eq!(
+        trace,
+        vec![
+            "in call `f(x - 1)` at test:1:28",
+            "in call `f(1)` at test:2:1"
+        ]
+    );
+}
+
+#[test]
+fn test_foreign_error() {
+    use crate::{parser, translator};
+    use std::sync::Arc;
+
+    let source = Arc::new(crate::diagnostic::Source::new(
+        "test",
+        "f: (xs) => List.concat(xs, 1),\nf([1])",
+    ));
+    let ast = parser::parse(&source).unwrap();
+    let program =
+        translator::get_cmd(source, &ast, Vec::new(), &crate::module::Library::default()).unwrap();
+    let e = run(program).unwrap_err().downcast::<Error>().unwrap();
+    assert_eq!(e.message, "argument 2: expected list, found number");
+    assert_eq!(e.location.unwrap().to_string(), "test:1:12");
+    let trace: Vec<_> = e.trace.iter().map(Frame::to_string).collect();
+    assert_eq!(trace, vec!["in call `f([1])` at test:2:1"]);
+}
+
+#[test]
+fn test_display_string() {
+    let s = Value::string(Rc::new("say \"hi\"\n\\\t\u{7}é".to_string()));
+    assert_eq!(s.to_string(), r#""say \"hi\"\n\\\t\u{7}é""#);
+}
+
+#[test]
+fn test_equality() {
+    use crate::translator::eval;
+
+    let cases = vec![
+        ("1 = 1", "true"),
+        ("0.1 + 0.2 = 0.3", "false"),
+        ("\"a\" = \"a\"", "true"),
+        ("\"a\" != \"b\"", "true"),
+        ("true = true", "true"),
+        ("(1 < 2) = false", "false"),
+        ("null = null", "true"),
+        ("null = 0", "false"),
+        ("1 = \"1\"", "false"),
+        ("[1, [2, \"x\"]] = [1, [2, \"x\"]]", "true"),
+        ("[1, 2] = [1]", "false"),
+        ("{a: 1, b: [2]} = {b: [2], a: 1}", "true"),
+        ("{a: 1} = {a: 2}", "false"),
+        ("{a: 1} = {b: 1}", "false"),
+        ("x: {a: 1 + 1}, x = {a: 2}", "true"),
+        ("a: {me: a, n: 1}, b: {me: b, n: 1}, a = b", "true"),
+        ("a: {me: a, n: 1}, b: {me: b, n: 2}, a = b", "false"),
+        ("f: (x) => x, f = f", "true"),
+        ("f: (x) => x, g: (x) => x, f = g", "false"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(eval(text).unwrap().to_string(), expected, "{}", text);
+    }
+
+    let e = eval("{a: [][0]} = {a: 1}").unwrap_err();
+    let e = e.downcast::<Error>().unwrap();
+    assert_eq!(e.message, "index 0 out of range for list of length 0");
+    assert_eq!(e.location.unwrap().to_string(), "test:1:5");
+}
+
+#[test]
+fn test_ordering() {
+    use crate::translator::eval;
+
+    let cases = vec![
+        ("\"a\" < \"b\"", "true"),
+        ("\"b\" <= \"a\"", "false"),
+        ("\"abc\" > \"ab\"", "true"),
+        ("[1, 2] < [1, 3]", "true"),
+        ("[1, 2] < [1, 2, 0]", "true"),
+        ("[\"b\"] >= [\"a\", \"z\"]", "true"),
+        ("(0 / 0) < 1", "false"),
+        ("(0 / 0) > 1", "false"),
+        ("compare(1, 2)", "-1"),
+        ("compare(\"b\", \"a\")", "1"),
+        ("compare([1, \"x\"], [1, \"x\"])", "0"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(eval(text).unwrap().to_string(), expected, "{}", text);
+    }
+
+    let error = |text| {
+        let e = eval(text).unwrap_err().downcast::<Error>().unwrap();
+        (e.message, e.location.unwrap().to_string())
+    };
+    assert_eq!(
+        error("\"a\" < 1"),
+        (
+            "cannot compare string with number".to_string(),
+            "test:1:5".to_string()
+        )
+    );
+    assert_eq!(
+        error("true > false"),
+        (
+            "cannot compare bool values".to_string(),
+            "test:1:6".to_string()
+        )
+    );
+    assert_eq!(
+        error("[1] < [\"a\"]"),
+        (
+            "cannot compare number with string".to_string(),
+            "test:1:5".to_string()
+        )
+    );
+    assert_eq!(
+        error("compare(1, null)"),
+        (
+            "cannot compare number with null".to_string(),
+            "test:1:1".to_string()
+        )
+    );
+}
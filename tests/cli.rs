@@ -0,0 +1,48 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn spctr(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_spctr"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_stdin_program() {
+    let output = spctr(&["-i"], "a: 1, {b: a + 1}");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "{b: 2}\n");
+
+    let output = spctr(&["-i"], "[][0]");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("<stdin>:1:1"));
+}
+
+#[test]
+fn test_raw_input() {
+    let output = spctr(&["-R", "-c", "[stdin, stdin = \"a\\nb\\n\"]"], "a\nb\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[\"a\\nb\\n\", true]\n"
+    );
+
+    let output = spctr(&["-R"], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("-R needs a program given by FILE or -c"));
+}